@@ -0,0 +1,1392 @@
+// Jackson Coxson
+//! The egui front end
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use egui::{Color32, ComboBox, RichText, TextEdit};
+use idevice::{IdeviceError, usbmuxd::UsbmuxdDevice};
+use log::{debug, error};
+use rfd::FileDialog;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+use crate::{
+    backend::{DiagnosticsQuery, PowerAction, PowerOptions},
+    commands::{DeviceKey, GuiCommands, IdeviceCommands, ListedDevice, RequestId, explain_error},
+    decode::DecodingStore,
+    diff::{Snapshot, changed_paths},
+    diff_view::{DiffAction, DiffView},
+    export::{DataEncoding, ExportFormat, export, load_dump},
+    index::RegistryIndex,
+    inspector_view::DataInspector,
+    model::Registry,
+    pairing::PairingStore,
+    pairing_view::PairingView,
+    plot_view::PlotView,
+    power::{PowerProgress, PowerStage},
+    registry::{KNOWN_PLANES, NodePath, registry_planes},
+    search::{Match, Query, search},
+    series::Series,
+    tree_view::{RegistryTree, TreeAction},
+};
+
+/// How long typing has to pause before the query is sent
+const QUERY_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// MobileGestalt keys asked for until the user picks their own
+const DEFAULT_GESTALT_KEYS: &str = "ProductType, HWModelStr, ChipID, SerialNumber, \
+    UniqueChipID, WifiAddress, BluetoothAddress, BatteryCurrentCapacity, BatteryIsCharging";
+
+/// Which diagnostics relay request the viewer shows the answer to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Tab {
+    #[default]
+    IORegistry,
+    MobileGestalt,
+    GasGauge,
+    WiFi,
+    NAND,
+    All,
+}
+
+impl Tab {
+    pub const ALL: [Self; 6] = [
+        Self::IORegistry,
+        Self::MobileGestalt,
+        Self::GasGauge,
+        Self::WiFi,
+        Self::NAND,
+        Self::All,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::IORegistry => "IORegistry",
+            Self::MobileGestalt => "MobileGestalt",
+            Self::GasGauge => "GasGauge",
+            Self::WiFi => "WiFi",
+            Self::NAND => "NAND",
+            Self::All => "All",
+        }
+    }
+}
+
+pub struct MyApp {
+    // Selector
+    devices: Option<BTreeMap<DeviceKey, ListedDevice>>,
+    devices_placeholder: String,
+    selected_device: Option<DeviceKey>,
+    /// What the selected device was called, kept for when it disconnects
+    selected_label: String,
+    device_info: Option<Vec<(String, String)>>,
+    /// Devices left out of the list, by UDID
+    unreachable: Vec<(String, IdeviceError)>,
+    device_error: Option<IdeviceError>,
+    query_error: Option<IdeviceError>,
+
+    current_ioregistry: Option<Registry>,
+    /// The plane the latest query asked for, which entry paths start with
+    query_plane: Option<String>,
+    /// Where the current registry came from, if it was opened rather than queried
+    opened_file: Option<PathBuf>,
+    open_error: Option<String>,
+    tree: RegistryTree,
+    save_error: Option<String>,
+    export_format: ExportFormat,
+    data_encoding: DataEncoding,
+
+    // Search
+    search: String,
+    search_filter: bool,
+    matches: Vec<Match>,
+    current_match: Option<usize>,
+
+    // Watch
+    watch: bool,
+    watch_interval: f32,
+    last_poll: Option<Instant>,
+    awaiting_poll: bool,
+    changed: Vec<NodePath>,
+    series: Vec<Series>,
+    plot: PlotView,
+
+    // Diff
+    diff: DiffView,
+    captures: usize,
+
+    pairing: PairingView,
+    inspector: DataInspector,
+
+    // Power
+    power: Option<PowerProgress>,
+    /// The action waiting on the user to confirm it
+    power_confirm: Option<PowerAction>,
+    power_options: PowerOptions,
+
+    // Inputs
+    tab: Tab,
+    /// Offered for the plane input, as the device lists them once it has
+    planes: Vec<String>,
+    /// Names and classes in the registry being shown
+    dump_index: RegistryIndex,
+    /// Names and classes of whole planes fetched, by UDID and plane
+    plane_indexes: HashMap<(String, String), RegistryIndex>,
+    /// The UDID and plane of a whole plane fetch in flight
+    indexing: Option<(String, String)>,
+    plane: String,
+    entry: String,
+    class: String,
+    /// Comma or space separated
+    gestalt_keys: String,
+    query_edited: Option<Instant>,
+    next_request: RequestId,
+    latest_request: Option<RequestId>,
+
+    // Channel
+    gui_recv: UnboundedReceiver<GuiCommands>,
+    idevice_sender: UnboundedSender<IdeviceCommands>,
+
+    /// Set when the idevice task crashed or stopped
+    backend_error: Option<String>,
+
+    show_logs: bool,
+}
+
+impl MyApp {
+    pub fn new(
+        gui_recv: UnboundedReceiver<GuiCommands>,
+        idevice_sender: UnboundedSender<IdeviceCommands>,
+    ) -> Self {
+        Self {
+            devices: None,
+            devices_placeholder: "Loading...".to_string(),
+            selected_device: None,
+            selected_label: "".to_string(),
+            device_info: None,
+            unreachable: Vec::new(),
+            device_error: None,
+            query_error: None,
+            gui_recv,
+            idevice_sender,
+            show_logs: false,
+            backend_error: None,
+            current_ioregistry: None,
+            query_plane: None,
+            opened_file: None,
+            open_error: None,
+            tree: RegistryTree::default(),
+            save_error: None,
+            export_format: ExportFormat::default(),
+            data_encoding: DataEncoding::default(),
+            search: "".to_string(),
+            search_filter: false,
+            matches: Vec::new(),
+            current_match: None,
+            watch: false,
+            watch_interval: 2.0,
+            last_poll: None,
+            awaiting_poll: false,
+            changed: Vec::new(),
+            series: Vec::new(),
+            plot: PlotView::default(),
+            diff: DiffView::default(),
+            captures: 0,
+            pairing: PairingView::new(None),
+            inspector: DataInspector::new(DecodingStore::default()),
+            power: None,
+            power_confirm: None,
+            power_options: PowerOptions::default(),
+            tab: Tab::default(),
+            planes: KNOWN_PLANES.map(String::from).to_vec(),
+            dump_index: RegistryIndex::default(),
+            plane_indexes: HashMap::new(),
+            indexing: None,
+            plane: "".to_string(),
+            entry: "".to_string(),
+            class: "".to_string(),
+            gestalt_keys: DEFAULT_GESTALT_KEYS.to_string(),
+            query_edited: None,
+            next_request: 0,
+            latest_request: None,
+        }
+    }
+
+    /// Keeps pairing files in `store`, without which they can't be imported
+    pub fn with_pairing_store(mut self, store: PairingStore) -> Self {
+        self.pairing = PairingView::new(Some(store));
+        self
+    }
+
+    /// Saves picked decodings to `store`, rather than forgetting them on exit
+    pub fn with_decoding_store(mut self, store: DecodingStore) -> Self {
+        self.inspector = DataInspector::new(store);
+        self
+    }
+
+    /// Applies every message the idevice task has sent since the last call
+    pub fn poll_backend(&mut self) {
+        loop {
+            match self.gui_recv.try_recv() {
+                Ok(msg) => self.handle_message(msg),
+                Err(tokio::sync::mpsc::error::TryRecvError::Empty) => break,
+                Err(tokio::sync::mpsc::error::TryRecvError::Disconnected) => {
+                    self.backend_stopped();
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Sends a command to the idevice task, noting if it has gone away
+    fn send(&mut self, command: IdeviceCommands) {
+        if self.idevice_sender.send(command).is_err() {
+            self.backend_stopped();
+        }
+    }
+
+    fn backend_stopped(&mut self) {
+        if self.backend_error.is_none() {
+            error!("The idevice task has stopped");
+        }
+        self.backend_error =
+            Some("The idevice task has stopped. Restart the explorer to reconnect.".to_string());
+        self.latest_request = None;
+        self.awaiting_poll = false;
+    }
+
+    /// Why devices can't be reached right now, if the idevice task crashed
+    pub fn backend_error(&self) -> Option<&str> {
+        self.backend_error.as_deref()
+    }
+
+    /// Applies a single message from the idevice task
+    pub fn handle_message(&mut self, msg: GuiCommands) {
+        match msg {
+            GuiCommands::NoUsbmuxd(idevice_error) => {
+                let install_msg = if cfg!(windows) {
+                    "Make sure you have iTunes installed from Apple's website, and that it's running."
+                } else if cfg!(target_os = "macos") {
+                    "usbmuxd should be running by default on MacOS. Please raise an issue on GitHub."
+                } else {
+                    "Make sure usbmuxd is installed and running."
+                };
+
+                self.devices_placeholder =
+                    format!("Failed to connect to usbmuxd! {install_msg}\n\n{idevice_error:#?}");
+            }
+            GuiCommands::Devices(devs) => {
+                let was_disconnected = self.is_disconnected();
+                if let Some(p) = &mut self.power {
+                    p.update(devs.keys().any(|k| k.udid == p.udid));
+                }
+                self.devices = Some(devs);
+                self.unreachable.clear();
+                if self.is_disconnected() && !was_disconnected {
+                    // Whatever is in flight to it won't come back
+                    self.latest_request = None;
+                    self.awaiting_poll = false;
+                    self.device_info = None;
+                } else if was_disconnected
+                    && let Some(key) = self.selected_device.clone()
+                    && !self.is_disconnected()
+                {
+                    self.select_device(&key);
+                }
+            }
+            GuiCommands::DeviceUnreachable(udid, e) => {
+                // Back, if not yet answering
+                if let Some(p) = &mut self.power
+                    && p.udid == udid
+                {
+                    p.update(true);
+                }
+                self.unreachable.push((udid, e));
+            }
+            GuiCommands::PowerSent(udid) => {
+                if let Some(p) = &mut self.power
+                    && p.udid == udid
+                {
+                    p.sent();
+                }
+            }
+            GuiCommands::PowerFailure(udid, e) => {
+                if let Some(p) = &mut self.power
+                    && p.udid == udid
+                {
+                    p.failed(explain_error(&e));
+                }
+            }
+            GuiCommands::DeviceInfo(info) => {
+                self.device_info = Some(info);
+                self.device_error = None;
+            }
+            GuiCommands::DeviceInfoFailure(e) => self.device_error = Some(e),
+            GuiCommands::GetDevicesFailure(idevice_error) => {
+                self.devices_placeholder = format!(
+                    "Failed to get list of connected devices from usbmuxd! {idevice_error:?}"
+                );
+            }
+            GuiCommands::BackendRestarted(reason) => {
+                self.backend_error = Some(format!(
+                    "The idevice task crashed and was restarted ({reason}). \
+                     Anything in progress was lost."
+                ));
+                // The restarted task won't answer anything sent before
+                self.latest_request = None;
+                self.awaiting_poll = false;
+                self.diff
+                    .capture_failed("The idevice task crashed during the capture".to_string());
+                self.send(IdeviceCommands::GetDevices);
+            }
+            GuiCommands::IORegistryFailure(id, e) => {
+                if self.latest_request != Some(id) {
+                    debug!("Dropping stale IORegistry failure {id}");
+                    return;
+                }
+                self.awaiting_poll = false;
+                self.diff.capture_failed(explain_error(&e));
+                self.query_error = Some(e);
+            }
+            GuiCommands::IORegistry(id, i) => {
+                if self.latest_request != Some(id) {
+                    debug!("Dropping stale IORegistry answer {id}");
+                    return;
+                }
+                self.query_error = None;
+                let i = i.map(|x| Registry::new(x, self.query_plane.as_deref()));
+                match &i {
+                    Some(reg) => {
+                        if let Some(slot) = self.diff.take_pending() {
+                            self.captures += 1;
+                            self.diff.set_snapshot(
+                                slot,
+                                Snapshot {
+                                    label: format!(
+                                        "{} (capture {})",
+                                        self.selected_label, self.captures
+                                    ),
+                                    registry: reg.clone(),
+                                },
+                            );
+                        }
+                    }
+                    None => self
+                        .diff
+                        .capture_failed("The device returned no registry for this query".into()),
+                }
+                // Only a re-poll of the same query is worth comparing against
+                self.changed = match (self.awaiting_poll, &self.current_ioregistry, &i) {
+                    (true, Some(old), Some(new)) => changed_paths(old, new),
+                    _ => Vec::new(),
+                };
+                if self.awaiting_poll
+                    && let Some(reg) = &i
+                {
+                    let now = SystemTime::now();
+                    for s in &mut self.series {
+                        s.record(reg, now);
+                    }
+                }
+                self.awaiting_poll = false;
+                self.tree.set_changed(&self.changed);
+                if let Some(key) = self.indexing.take()
+                    && let Some(reg) = &i
+                {
+                    self.plane_indexes.insert(key, RegistryIndex::build(reg));
+                }
+                self.opened_file = None;
+                self.current_ioregistry = i;
+                self.tree.invalidate();
+                self.update_search();
+                self.update_completions();
+            }
+            GuiCommands::Planes(udid, planes) => {
+                if self.selected_device().is_some_and(|d| d.udid == udid) {
+                    self.planes = planes;
+                }
+            }
+        }
+    }
+
+    /// Selects a device connection and requests its info
+    pub fn select_device(&mut self, key: &DeviceKey) {
+        self.selected_device = Some(key.clone());
+        self.device_info = None;
+        self.device_error = None;
+        self.query_error = None;
+        if let Some(d) = self.devices.as_ref().and_then(|x| x.get(key)) {
+            self.selected_label = device_label(self.devices.as_ref().unwrap(), key, d);
+        }
+        if let Some(dev) = self.selected_device().cloned() {
+            self.planes = KNOWN_PLANES.map(String::from).to_vec();
+            self.send(IdeviceCommands::GetDeviceInfo(dev.clone()));
+            self.send(IdeviceCommands::GetPlanes(dev));
+        }
+    }
+
+    /// Restarts, shuts down or sleeps the selected device, following it until it's done
+    pub fn power(&mut self, action: PowerAction, options: PowerOptions) {
+        let Some(dev) = self.selected_device().cloned() else {
+            return;
+        };
+        self.power = Some(PowerProgress::new(&dev.udid, action));
+        self.send(IdeviceCommands::Power(dev, action, options));
+    }
+
+    /// The last power action, until dismissed
+    pub fn power_progress(&self) -> Option<&PowerProgress> {
+        self.power.as_ref()
+    }
+
+    /// Sets the query inputs, as if typed into the text boxes
+    pub fn set_query(&mut self, plane: &str, entry: &str, class: &str) {
+        self.plane = plane.to_string();
+        self.entry = entry.to_string();
+        self.class = class.to_string();
+    }
+
+    /// Sets the query inputs as if typed, sending once typing pauses
+    pub fn edit_query(&mut self, plane: &str, entry: &str, class: &str) {
+        self.set_query(plane, entry, class);
+        self.query_edited = Some(Instant::now());
+    }
+
+    /// Sets the MobileGestalt keys as if typed, sending once typing pauses
+    pub fn edit_gestalt_keys(&mut self, keys: &str) {
+        self.gestalt_keys = keys.to_string();
+        self.query_edited = Some(Instant::now());
+    }
+
+    /// The MobileGestalt keys that will be asked for
+    pub fn gestalt_keys(&self) -> Vec<String> {
+        self.gestalt_keys
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|k| !k.is_empty())
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    /// Switches the viewer to another request, sending it for the selected device
+    pub fn set_tab(&mut self, tab: Tab) {
+        if self.tab == tab {
+            return;
+        }
+        self.tab = tab;
+        self.query_edited = None;
+        self.awaiting_poll = false;
+        if self.selected_device().is_none() {
+            return;
+        }
+        // The last tab's answer would be compared against, or shown as this one's
+        self.changed.clear();
+        self.tree.set_changed(&self.changed);
+        self.opened_file = None;
+        self.current_ioregistry = None;
+        self.tree.invalidate();
+        self.update_search();
+        self.send_query();
+    }
+
+    pub fn tab(&self) -> Tab {
+        self.tab
+    }
+
+    /// Sends an edited query once it has been left alone long enough
+    ///
+    /// Returns how long until it is due, if one is waiting.
+    pub fn poll_debounce(&mut self) -> Option<Duration> {
+        let elapsed = self.query_edited?.elapsed();
+        if elapsed < QUERY_DEBOUNCE {
+            return Some(QUERY_DEBOUNCE - elapsed);
+        }
+        self.query_edited = None;
+        // Nothing to compare a different query against
+        self.awaiting_poll = false;
+        self.send_query();
+        None
+    }
+
+    /// Sends the current tab's request for the selected device using the current inputs
+    ///
+    /// Answers to anything sent before this are dropped.
+    pub fn send_query(&mut self) {
+        let Some(dev) = self.selected_device().cloned() else {
+            return;
+        };
+        let non_empty = |s: &String| if s.is_empty() { None } else { Some(s.clone()) };
+        let id = self.next_request;
+        self.next_request += 1;
+        self.latest_request = Some(id);
+        self.indexing = None;
+        self.query_plane = None;
+        let query = match self.tab {
+            Tab::IORegistry => {
+                self.query_plane = non_empty(&self.plane);
+                if !self.plane.is_empty() && self.entry.is_empty() && self.class.is_empty() {
+                    self.indexing = Some((dev.udid.clone(), self.plane.clone()));
+                }
+                let cmd = IdeviceCommands::IORegistsry(
+                    id,
+                    (
+                        dev,
+                        non_empty(&self.plane),
+                        non_empty(&self.entry),
+                        non_empty(&self.class),
+                    ),
+                );
+                self.send(cmd);
+                return;
+            }
+            Tab::MobileGestalt => DiagnosticsQuery::MobileGestalt(self.gestalt_keys()),
+            Tab::GasGauge => DiagnosticsQuery::GasGauge,
+            Tab::WiFi => DiagnosticsQuery::WiFi,
+            Tab::NAND => DiagnosticsQuery::NAND,
+            Tab::All => DiagnosticsQuery::All,
+        };
+        self.send(IdeviceCommands::Diagnostics(id, (dev, query)));
+    }
+
+    /// The ID of the last query sent, whose answer is the only one shown
+    pub fn latest_request(&self) -> Option<RequestId> {
+        self.latest_request
+    }
+
+    /// Turns re-polling the current query on or off
+    pub fn set_watch(&mut self, watch: bool, interval: Duration) {
+        self.watch = watch;
+        self.watch_interval = interval.as_secs_f32();
+        self.last_poll = None;
+    }
+
+    /// Re-sends the query if watching and the interval has passed
+    ///
+    /// Returns how long until the next poll is due.
+    pub fn poll_watch(&mut self) -> Option<Duration> {
+        // Don't poll a half typed query
+        if !self.watch || self.selected_device().is_none() || self.query_edited.is_some() {
+            return None;
+        }
+        let interval = Duration::from_secs_f32(self.watch_interval);
+        if let Some(last) = self.last_poll {
+            let elapsed = last.elapsed();
+            // A relay that never answered shouldn't stall the watch for good
+            let stalled = elapsed >= (interval * 4).max(Duration::from_secs(10));
+            if elapsed < interval || (self.awaiting_poll && !stalled) {
+                return Some(interval.saturating_sub(elapsed));
+            }
+        }
+        self.send_query();
+        self.awaiting_poll = true;
+        self.last_poll = Some(Instant::now());
+        Some(interval)
+    }
+
+    /// Shows a saved dump in place of the live registry, with no device needed
+    pub fn open_file(&mut self, path: &Path) -> Result<(), String> {
+        let reg = load_dump(path)?;
+        // Nothing in flight or scheduled may replace the file
+        self.latest_request = None;
+        self.query_edited = None;
+        self.watch = false;
+        self.awaiting_poll = false;
+        self.changed.clear();
+        self.tree.set_changed(&self.changed);
+
+        self.opened_file = Some(path.to_path_buf());
+        self.current_ioregistry = Some(reg);
+        self.tree.invalidate();
+        self.update_search();
+        self.update_completions();
+        Ok(())
+    }
+
+    pub fn opened_file(&self) -> Option<&Path> {
+        self.opened_file.as_deref()
+    }
+
+    /// Chooses the format "Save to File" writes
+    pub fn set_export_format(&mut self, format: ExportFormat, data_encoding: DataEncoding) {
+        self.export_format = format;
+        self.data_encoding = data_encoding;
+    }
+
+    /// Writes the current registry to `path` in the chosen format
+    pub fn save_to(&self, path: &Path) -> Result<(), String> {
+        let reg = self
+            .current_ioregistry
+            .as_ref()
+            .ok_or("No registry loaded")?;
+        let bytes = export(reg, self.export_format, self.data_encoding)?;
+        std::fs::write(path, bytes).map_err(|e| e.to_string())
+    }
+
+    /// Pins a number in the current registry for plotting, or unpins it
+    pub fn toggle_pin(&mut self, path: &NodePath) {
+        if let Some(i) = self.series.iter().position(|s| &s.path == path) {
+            self.series.remove(i);
+        } else if let Some(reg) = &self.current_ioregistry
+            && let Some(s) = Series::pin(reg, path, SystemTime::now())
+        {
+            self.series.push(s);
+        }
+    }
+
+    pub fn series(&self) -> &[Series] {
+        &self.series
+    }
+
+    /// Paths that changed between the last two polls
+    pub fn changed(&self) -> &[NodePath] {
+        &self.changed
+    }
+
+    /// Queries the selected device again and stores the result as a diff snapshot
+    pub fn capture_snapshot(&mut self, slot: usize) {
+        if self.selected_device().is_none() {
+            return;
+        }
+        self.diff.set_pending(slot);
+        self.send_query();
+    }
+
+    /// How the selected device is shown, e.g. `iPhone (USB)`
+    pub fn selected_label(&self) -> &str {
+        &self.selected_label
+    }
+
+    pub fn diff(&self) -> &DiffView {
+        &self.diff
+    }
+
+    pub fn inspector(&self) -> &DataInspector {
+        &self.inspector
+    }
+
+    pub fn pairing(&self) -> &PairingView {
+        &self.pairing
+    }
+
+    pub fn diff_mut(&mut self) -> &mut DiffView {
+        &mut self.diff
+    }
+
+    /// Searches the current registry, as if typed into the search box
+    pub fn set_search(&mut self, query: &str, filter: bool) {
+        self.search = query.to_string();
+        self.search_filter = filter;
+        self.update_search();
+    }
+
+    fn update_search(&mut self) {
+        // Keep the current match across re-polls if it's still there
+        let current = self.current_match().map(|m| m.path.clone());
+        self.matches = match (Query::parse(&self.search), &self.current_ioregistry) {
+            (Some(q), Some(reg)) => search(reg, &q),
+            _ => Vec::new(),
+        };
+        self.tree.set_matches(&self.matches, self.search_filter);
+        self.current_match = current.and_then(|p| self.matches.iter().position(|m| m.path == p));
+    }
+
+    /// Refreshes what the inputs offer from the registry being shown
+    fn update_completions(&mut self) {
+        let Some(reg) = &self.current_ioregistry else {
+            return;
+        };
+        let planes = registry_planes(&reg.root().properties);
+        if !planes.is_empty() {
+            self.planes = planes;
+        }
+        // Other diagnostics have no entries to index
+        if self.tab == Tab::IORegistry || self.opened_file.is_some() {
+            self.dump_index = RegistryIndex::build(reg);
+        }
+    }
+
+    /// The index of the whole plane being queried if it has been fetched, or
+    /// else of the registry being shown
+    fn completion_index(&self) -> &RegistryIndex {
+        pick_index(
+            &self.plane_indexes,
+            &self.dump_index,
+            self.selected_device().map(|d| d.udid.as_str()),
+            &self.plane,
+        )
+    }
+
+    /// Entry names offered for the name input
+    pub fn entry_names(&self) -> Vec<&str> {
+        self.completion_index().names().collect()
+    }
+
+    /// Planes offered for the plane input
+    pub fn planes(&self) -> &[String] {
+        &self.planes
+    }
+
+    /// Classes offered for the class input
+    pub fn classes(&self) -> Vec<&str> {
+        self.completion_index().classes().collect()
+    }
+
+    /// How many entries the typed query would find, once its whole plane has been fetched
+    pub fn match_count(&self) -> Option<usize> {
+        let udid = self.selected_device()?.udid.clone();
+        let index = self.plane_indexes.get(&(udid, self.plane.clone()))?;
+        let non_empty = |s: &str| (!s.is_empty()).then(|| s.to_string());
+        let (name, class) = (non_empty(&self.entry), non_empty(&self.class));
+        if name.is_none() && class.is_none() {
+            return None;
+        }
+        Some(index.count(name.as_deref(), class.as_deref()))
+    }
+
+    /// Moves to the next or previous match, wrapping around
+    pub fn jump_to_match(&mut self, forward: bool) {
+        let n = self.matches.len();
+        if n == 0 {
+            return;
+        }
+        let i = match (self.current_match, forward) {
+            (None, true) => 0,
+            (None, false) => n - 1,
+            (Some(i), true) => (i + 1) % n,
+            (Some(i), false) => (i + n - 1) % n,
+        };
+        self.current_match = Some(i);
+        self.tree.reveal(&self.matches[i].path);
+    }
+
+    pub fn matches(&self) -> &[Match] {
+        &self.matches
+    }
+
+    pub fn current_match(&self) -> Option<&Match> {
+        self.current_match.map(|i| &self.matches[i])
+    }
+
+    pub fn selected_device(&self) -> Option<&UsbmuxdDevice> {
+        let key = self.selected_device.as_ref()?;
+        self.devices
+            .as_ref()
+            .and_then(|x| x.get(key))
+            .map(|d| &d.device)
+    }
+
+    pub fn selected_key(&self) -> Option<&DeviceKey> {
+        self.selected_device.as_ref()
+    }
+
+    /// Whether the selected device has been unplugged
+    ///
+    /// It is selected again if it comes back, and watching resumes.
+    pub fn is_disconnected(&self) -> bool {
+        self.selected_device.is_some() && self.selected_device().is_none()
+    }
+
+    /// Devices that are connected but couldn't be listed, and why
+    pub fn unreachable(&self) -> &[(String, IdeviceError)] {
+        &self.unreachable
+    }
+
+    /// Why the selected device's info couldn't be read
+    pub fn device_error(&self) -> Option<&IdeviceError> {
+        self.device_error.as_ref()
+    }
+
+    /// Why the last query failed, until one succeeds
+    pub fn query_error(&self) -> Option<&IdeviceError> {
+        self.query_error.as_ref()
+    }
+
+    pub fn devices(&self) -> Option<&BTreeMap<DeviceKey, ListedDevice>> {
+        self.devices.as_ref()
+    }
+
+    /// Text shown in place of the device selector while there are no devices
+    pub fn devices_placeholder(&self) -> &str {
+        &self.devices_placeholder
+    }
+
+    pub fn device_info(&self) -> Option<&[(String, String)]> {
+        self.device_info.as_deref()
+    }
+
+    pub fn current_ioregistry(&self) -> Option<&Registry> {
+        self.current_ioregistry.as_ref()
+    }
+}
+
+impl eframe::App for MyApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        // Get updates from the idevice thread
+        self.poll_backend();
+        let debounce = self.poll_debounce();
+        if let Some(next) = debounce.into_iter().chain(self.poll_watch()).min() {
+            ctx.request_repaint_after(next);
+        }
+
+        if self.show_logs {
+            egui::Window::new("logs")
+                .open(&mut self.show_logs)
+                .show(ctx, |ui| {
+                    egui_logger::logger_ui()
+                        .warn_color(Color32::BLACK) // the yellow is too bright in dark mode
+                        .log_levels([true, true, true, true, false])
+                        .enable_category("idevice".to_string(), true)
+                        // there should be a way to set default false...
+                        .enable_category("mdns::mdns".to_string(), false)
+                        .enable_category("eframe".to_string(), false)
+                        .enable_category("eframe::native::glow_integration".to_string(), false)
+                        .enable_category("egui_glow::shader_version".to_string(), false)
+                        .enable_category("egui_glow::vao".to_string(), false)
+                        .enable_category("egui_glow::painter".to_string(), false)
+                        .enable_category("rustls::client::hs".to_string(), false)
+                        .enable_category("rustls::client::tls12".to_string(), false)
+                        .enable_category("rustls::client::common".to_string(), false)
+                        .enable_category("idevice_pair::discover".to_string(), false)
+                        .enable_category("reqwest::connect".to_string(), false)
+                        .show(ui);
+                });
+        }
+        if self.diff.open
+            && let Some(DiffAction::Capture(slot)) =
+                self.diff.show(ctx, self.selected_device().is_some())
+        {
+            self.capture_snapshot(slot);
+        }
+        if self.inspector.open {
+            self.inspector.show(ctx, self.current_ioregistry.as_ref());
+        }
+        let selected_udid = self.selected_device().map(|d| d.udid.clone());
+        if self.pairing.open && self.pairing.show(ctx, selected_udid.as_deref()) {
+            self.send(IdeviceCommands::GetDevices);
+        }
+        if let Some(action) = self.power_confirm {
+            let mut confirmed = None;
+            let modal = egui::Modal::new(egui::Id::new("power_confirm")).show(ctx, |ui| {
+                ui.heading(format!("{} {}?", action.name(), self.selected_label));
+                if action.detaches() {
+                    ui.checkbox(
+                        &mut self.power_options.wait_for_disconnect,
+                        "Wait for every connection to close first",
+                    );
+                    ui.checkbox(
+                        &mut self.power_options.show_notification,
+                        "Show a notification on the device",
+                    );
+                }
+                ui.horizontal(|ui| {
+                    if ui.button(action.name()).clicked() {
+                        confirmed = Some(true);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        confirmed = Some(false);
+                    }
+                });
+            });
+            if modal.should_close() && confirmed.is_none() {
+                confirmed = Some(false);
+            }
+            if let Some(confirmed) = confirmed {
+                self.power_confirm = None;
+                if confirmed {
+                    self.power(action, self.power_options);
+                }
+            }
+        }
+        egui::TopBottomPanel::top("controls").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.heading("IORegistry Explorer");
+                ui.separator();
+                let p_background_color = match ctx.theme() {
+                    egui::Theme::Dark => Color32::BLACK,
+                    egui::Theme::Light => Color32::LIGHT_GRAY,
+                };
+                egui::frame::Frame::new()
+                    .corner_radius(3)
+                    .inner_margin(3)
+                    .fill(p_background_color)
+                    .show(ui, |ui| {
+                        ui.toggle_value(&mut self.show_logs, "logs");
+                        ui.toggle_value(&mut self.diff.open, "diff");
+                        ui.toggle_value(&mut self.pairing.open, "pairing");
+                    });
+                ui.separator();
+                if ui.button("Open File...").clicked()
+                    && let Some(p) = FileDialog::new()
+                        .add_filter("Registry dump", &["plist", "json", "yaml", "yml"])
+                        .pick_file()
+                {
+                    self.open_error = self.open_file(&p).err();
+                }
+                if let Some(p) = &self.opened_file {
+                    ui.label(format!("Viewing {}", p.display()));
+                    if ui.button("Close").clicked() {
+                        self.opened_file = None;
+                        self.current_ioregistry = None;
+                        self.tree.invalidate();
+                        self.update_search();
+                    }
+                }
+                if let Some(e) = &self.open_error {
+                    ui.label(RichText::new(e).color(Color32::RED));
+                }
+            });
+            if let Some(e) = &self.backend_error {
+                let mut dismiss = false;
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new(e).color(Color32::RED));
+                    dismiss = ui.button("Dismiss").clicked();
+                });
+                if dismiss {
+                    self.backend_error = None;
+                }
+            }
+            let mut newly_selected = None;
+            let mut retry_info = false;
+            let mut retry_list = false;
+            match &self.devices {
+                Some(devs) => {
+                    if devs.is_empty() {
+                        ui.label("No devices connected! Plug one in via USB.");
+                    } else {
+                        ui.horizontal(|ui| {
+                            ui.vertical(|ui| {
+                                ui.label("Choose a device");
+                                let selected = self
+                                    .selected_device
+                                    .as_ref()
+                                    .and_then(|k| Some(device_label(devs, k, devs.get(k)?)))
+                                    .unwrap_or_default();
+                                ComboBox::from_label("").selected_text(selected).show_ui(
+                                    ui,
+                                    |ui| {
+                                        for (key, dev) in devs {
+                                            if ui
+                                                .selectable_label(
+                                                    self.selected_device.as_ref() == Some(key),
+                                                    device_label(devs, key, dev),
+                                                )
+                                                .on_hover_text(&key.udid)
+                                                .clicked()
+                                            {
+                                                newly_selected = Some(key.clone());
+                                            };
+                                        }
+                                    },
+                                );
+                            });
+
+                            ui.separator();
+
+                            // Show device info to the right if available
+                            if let Some(info) = &self.device_info {
+                                ui.vertical(|ui| {
+                                    for (key, value) in info {
+                                        ui.horizontal(|ui| {
+                                            ui.label(format!("{key}:"));
+                                            ui.label(value);
+                                        });
+                                    }
+                                });
+                            } else if let Some(e) = &self.device_error {
+                                retry_info = error_line(ui, "Couldn't read device info", e);
+                            }
+                        });
+                    }
+                    for (udid, e) in &self.unreachable {
+                        retry_list |= error_line(ui, &format!("{udid} isn't listed"), e);
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("Refresh...").clicked() {
+                            retry_list = true;
+                        }
+                        let busy = self.power.as_ref().is_some_and(|p| !p.is_finished());
+                        ui.add_enabled_ui(self.selected_device().is_some() && !busy, |ui| {
+                            ui.menu_button("Power", |ui| {
+                                for a in PowerAction::ALL {
+                                    if ui.button(format!("{}...", a.name())).clicked() {
+                                        self.power_confirm = Some(a);
+                                    }
+                                }
+                            });
+                        });
+                    });
+                }
+                None => {
+                    ui.label(&self.devices_placeholder);
+                    retry_list =
+                        self.devices_placeholder != "Loading..." && ui.button("Retry").clicked();
+                }
+            }
+            if let Some(key) = newly_selected {
+                self.select_device(&key);
+            }
+            if retry_info && let Some(key) = self.selected_device.clone() {
+                self.select_device(&key);
+            }
+            if retry_list {
+                self.send(IdeviceCommands::GetDevices);
+            }
+            let mut dismiss_power = false;
+            if let Some(p) = &self.power {
+                ui.horizontal(|ui| {
+                    let color = match p.stage {
+                        PowerStage::Failed(_) => Color32::RED,
+                        _ => ui.visuals().text_color(),
+                    };
+                    if !p.is_finished() {
+                        ui.spinner();
+                        // Keep the elapsed time ticking
+                        ctx.request_repaint_after(Duration::from_secs(1));
+                    }
+                    ui.label(RichText::new(p.describe()).color(color));
+                    dismiss_power = p.is_finished() && ui.button("Dismiss").clicked();
+                });
+            }
+            if dismiss_power {
+                self.power = None;
+            }
+            if self.devices.is_some() && self.is_disconnected() {
+                ui.label(
+                    RichText::new(format!(
+                        "{} disconnected. It will be selected again when it comes back.",
+                        self.selected_label
+                    ))
+                    .color(Color32::RED),
+                );
+            }
+
+            ui.separator();
+
+            let has_device = self.selected_device().is_some();
+            if has_device || self.current_ioregistry.is_some() {
+                ui.separator();
+                let mut query_changed = false;
+                let udid = self.selected_device().map(|d| d.udid.clone());
+                let match_count = self.match_count();
+                if has_device {
+                    let mut tab = self.tab;
+                    ui.horizontal(|ui| {
+                        for t in Tab::ALL {
+                            ui.selectable_value(&mut tab, t, t.name());
+                        }
+                    });
+                    self.set_tab(tab);
+                }
+                ui.horizontal(|ui| {
+                    if !has_device {
+                        return;
+                    }
+                    match self.tab {
+                        Tab::IORegistry => {
+                            ui.vertical(|ui| {
+                                ui.heading("Plane");
+                                ui.label("Entry Plane");
+                                ui.horizontal(|ui| {
+                                    let planes = self.planes.iter().map(String::as_str);
+                                    query_changed |= completing_edit(ui, &mut self.plane, planes);
+                                    ui.menu_button("⏷", |ui| {
+                                        for p in &self.planes {
+                                            if ui.button(p).clicked() {
+                                                self.plane = p.clone();
+                                                query_changed = true;
+                                            }
+                                        }
+                                    })
+                                    .response
+                                    .on_hover_text("Planes this device has");
+                                });
+                            });
+                            ui.separator();
+                            ui.vertical(|ui| {
+                                ui.heading("Name");
+                                ui.label("Entry Name");
+                                let index = pick_index(
+                                    &self.plane_indexes,
+                                    &self.dump_index,
+                                    udid.as_deref(),
+                                    &self.plane,
+                                );
+                                query_changed |=
+                                    completing_edit(ui, &mut self.entry, index.names());
+                            });
+                            ui.separator();
+                            ui.vertical(|ui| {
+                                ui.heading("Class");
+                                ui.label("Entry Class");
+                                let index = pick_index(
+                                    &self.plane_indexes,
+                                    &self.dump_index,
+                                    udid.as_deref(),
+                                    &self.plane,
+                                );
+                                query_changed |=
+                                    completing_edit(ui, &mut self.class, index.classes());
+                            });
+                            ui.separator();
+                            if let Some(n) = match_count {
+                                let text = match n {
+                                    0 => RichText::new("No entries match").color(Color32::RED),
+                                    1 => RichText::new("1 entry matches"),
+                                    n => RichText::new(format!("{n} entries match")),
+                                };
+                                ui.label(text).on_hover_text(format!(
+                                    "In the last fetch of the whole {} plane",
+                                    self.plane
+                                ));
+                                ui.separator();
+                            }
+                        }
+                        Tab::MobileGestalt => {
+                            ui.vertical(|ui| {
+                                ui.heading("Keys");
+                                ui.label("Comma separated");
+                                query_changed |= ui
+                                    .add(
+                                        TextEdit::multiline(&mut self.gestalt_keys)
+                                            .desired_rows(2)
+                                            .desired_width(400.0),
+                                    )
+                                    .changed();
+                            });
+                            ui.separator();
+                        }
+                        _ => {}
+                    }
+                    ui.vertical(|ui| {
+                        ui.heading("Watch");
+                        ui.horizontal(|ui| {
+                            if ui.checkbox(&mut self.watch, "Re-poll every").changed() {
+                                self.last_poll = None;
+                            }
+                            ui.add(
+                                egui::DragValue::new(&mut self.watch_interval)
+                                    .range(0.5..=60.0)
+                                    .speed(0.1)
+                                    .suffix(" s"),
+                            );
+                        });
+                        if self.watch && !self.changed.is_empty() {
+                            ui.label(format!("{} values changed", self.changed.len()));
+                        }
+                    });
+                });
+                if has_device
+                    && let Some(e) = &self.query_error
+                    && error_line(ui, "Query failed", e)
+                {
+                    self.send_query();
+                }
+                ui.horizontal(|ui| {
+                    if self.current_ioregistry.is_none() {
+                        return;
+                    }
+                    ui.vertical(|ui| {
+                        ui.heading("Save to File");
+                        if let Some(msg) = &self.save_error {
+                            ui.label(RichText::new(msg).color(Color32::RED));
+                        }
+                        ComboBox::from_id_salt("export_format")
+                            .selected_text(self.export_format.name())
+                            .show_ui(ui, |ui| {
+                                for f in ExportFormat::ALL {
+                                    ui.selectable_value(&mut self.export_format, f, f.name());
+                                }
+                            });
+                        ui.add_enabled_ui(self.export_format.encodes_data(), |ui| {
+                            ComboBox::from_id_salt("data_encoding")
+                                .selected_text(format!("Data as {}", self.data_encoding.name()))
+                                .show_ui(ui, |ui| {
+                                    for e in DataEncoding::ALL {
+                                        ui.selectable_value(&mut self.data_encoding, e, e.name());
+                                    }
+                                });
+                        });
+                        if ui
+                            .add_enabled(
+                                self.current_ioregistry.is_some(),
+                                egui::Button::new("Save to File"),
+                            )
+                            .clicked()
+                            && let Some(p) = FileDialog::new()
+                                .set_can_create_directories(true)
+                                .set_title(format!("Save {}", self.tab.name()))
+                                .set_file_name(format!(
+                                    "{}.{}",
+                                    match self.tab {
+                                        Tab::IORegistry => "ioreg".to_string(),
+                                        t => t.name().to_lowercase(),
+                                    },
+                                    self.export_format.extension()
+                                ))
+                                .save_file()
+                        {
+                            self.save_error = self.save_to(&p).err();
+                        }
+                    });
+                });
+                if query_changed {
+                    self.query_edited = Some(Instant::now());
+                }
+            }
+        });
+
+        if !self.series.is_empty() {
+            egui::TopBottomPanel::bottom("plots")
+                .resizable(true)
+                .default_height(250.0)
+                .show(ctx, |ui| {
+                    self.plot.show(ui, &mut self.series);
+                });
+        }
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            if self.current_ioregistry.is_none() {
+                return;
+            }
+
+            let mut search_changed = false;
+            let mut jump = None;
+            ui.horizontal(|ui| {
+                let response = ui.add(
+                    TextEdit::singleline(&mut self.search)
+                        .hint_text("Search names, classes, keys, values or key=value"),
+                );
+                search_changed |= response.changed();
+                if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                    jump = Some(true);
+                    response.request_focus();
+                }
+                if ui.button("⏶").on_hover_text("Previous match").clicked() {
+                    jump = Some(false);
+                }
+                if ui.button("⏷").on_hover_text("Next match").clicked() {
+                    jump = Some(true);
+                }
+                search_changed |= ui
+                    .checkbox(&mut self.search_filter, "Only matching")
+                    .changed();
+                if !self.search.trim().is_empty() {
+                    match (self.matches.len(), self.current_match) {
+                        (0, _) => ui.label("No matches"),
+                        (n, Some(i)) => ui.label(format!("{}/{n}", i + 1)),
+                        (n, None) => ui.label(format!("{n} matches")),
+                    };
+                }
+            });
+            if search_changed {
+                self.update_search();
+            }
+            if let Some(forward) = jump {
+                self.jump_to_match(forward);
+            }
+
+            let mut action = None;
+            if let Some(ioreg) = &self.current_ioregistry {
+                let p_background_color = match ctx.theme() {
+                    egui::Theme::Dark => Color32::BLACK,
+                    egui::Theme::Light => Color32::LIGHT_GRAY,
+                };
+                egui::frame::Frame::new()
+                    .corner_radius(10)
+                    .inner_margin(10)
+                    .fill(p_background_color)
+                    .show(ui, |ui| {
+                        let pinned: Vec<_> = self.series.iter().map(|s| s.path.clone()).collect();
+                        action = self.tree.show(ui, ioreg, &pinned);
+                    });
+            }
+            match action {
+                Some(TreeAction::Pin(path)) => self.toggle_pin(&path),
+                Some(TreeAction::Inspect(path)) => self.inspector.inspect(&path),
+                None => {}
+            }
+        });
+    }
+}
+
+/// A device's label, with part of its UDID if another device has the same name
+fn device_label(
+    devs: &BTreeMap<DeviceKey, ListedDevice>,
+    key: &DeviceKey,
+    dev: &ListedDevice,
+) -> String {
+    let ambiguous = devs
+        .iter()
+        .any(|(k, d)| k.udid != key.udid && d.name == dev.name);
+    if ambiguous {
+        // By chars, as a UDID from a misbehaving usbmuxd needn't be ASCII
+        let start = key.udid.char_indices().rev().nth(5).map_or(0, |(i, _)| i);
+        let udid = &key.udid[start..];
+        format!("{} [{udid}]", dev.label())
+    } else {
+        dev.label()
+    }
+}
+
+/// Most suggestions a completing text box lists at once
+const MAX_SUGGESTIONS: usize = 12;
+
+/// A text box listing the options containing what's typed, returning whether it changed
+fn completing_edit<'a>(
+    ui: &mut egui::Ui,
+    text: &mut String,
+    options: impl IntoIterator<Item = &'a str>,
+) -> bool {
+    let response = ui.add(TextEdit::singleline(text));
+    let mut changed = response.changed();
+    let popup_id = egui::Popup::default_response_id(&response);
+    // Indexes can be big, only filter them while being typed into
+    if !response.has_focus() && !egui::Popup::is_id_open(ui.ctx(), popup_id) {
+        return changed;
+    }
+    let typed = text.to_lowercase();
+    let suggestions: Vec<_> = options
+        .into_iter()
+        .filter(|o| o.to_lowercase().contains(&typed) && *o != text.as_str())
+        .take(MAX_SUGGESTIONS)
+        .collect();
+    if suggestions.is_empty() {
+        return changed;
+    }
+    let open = (response.gained_focus() || response.changed()).then_some(true);
+    egui::Popup::from_response(&response)
+        .open_memory(open.map(egui::SetOpenCommand::Bool))
+        .close_behavior(egui::PopupCloseBehavior::CloseOnClickOutside)
+        .show(|ui| {
+            for s in suggestions {
+                if ui.selectable_label(false, s).clicked() {
+                    *text = s.to_string();
+                    changed = true;
+                    egui::Popup::close_all(ui.ctx());
+                }
+            }
+        });
+    changed
+}
+
+/// The index of a fetched plane for this device, or else `dump`
+fn pick_index<'a>(
+    plane_indexes: &'a HashMap<(String, String), RegistryIndex>,
+    dump: &'a RegistryIndex,
+    udid: Option<&str>,
+    plane: &str,
+) -> &'a RegistryIndex {
+    udid.and_then(|u| plane_indexes.get(&(u.to_string(), plane.to_string())))
+        .unwrap_or(dump)
+}
+
+/// Shows an error with what to do about it, returning whether Retry was clicked
+fn error_line(ui: &mut egui::Ui, what: &str, e: &IdeviceError) -> bool {
+    ui.horizontal(|ui| {
+        ui.label(RichText::new(format!("{what}: {}", explain_error(e))).color(Color32::RED))
+            .on_hover_text(format!("{e:?}"));
+        ui.button("Retry").clicked()
+    })
+    .inner
+}
@@ -0,0 +1,381 @@
+// Jackson Coxson
+//! In-process backend serving canned data, for running without hardware
+
+use std::{
+    io,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use idevice::{
+    IdeviceError,
+    usbmuxd::{Connection, UsbmuxdDevice},
+};
+
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::{DiagnosticsQuery, PowerAction, PowerOptions, RegistryBackend};
+
+/// Failures the mock can be told to produce
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockFault {
+    /// usbmuxd can't be reached at all
+    NoUsbmuxd,
+    /// usbmuxd answers, but the device list is garbage
+    BadDeviceList,
+    /// lockdownd refuses the connection
+    LockdownRefused,
+    /// The device is passcode locked
+    DeviceLocked,
+    /// The diagnostics relay never answers
+    RelayTimeout,
+    /// The relay answers, but without the dictionary asked for
+    NoRegistry,
+    /// The backend panics, as on a response it can't handle
+    Panic,
+}
+
+impl MockFault {
+    /// The error the fault fails with, or `None` if it answers without what was asked
+    fn to_error(self) -> Option<IdeviceError> {
+        Some(match self {
+            MockFault::NoUsbmuxd => {
+                IdeviceError::Socket(io::Error::from(io::ErrorKind::ConnectionRefused))
+            }
+            MockFault::BadDeviceList => IdeviceError::UnexpectedResponse,
+            MockFault::LockdownRefused => IdeviceError::UsbConnectionRefused,
+            MockFault::DeviceLocked => IdeviceError::DeviceLocked,
+            MockFault::RelayTimeout => {
+                IdeviceError::Socket(io::Error::from(io::ErrorKind::TimedOut))
+            }
+            MockFault::NoRegistry => return None,
+            MockFault::Panic => panic!("mock backend panicked"),
+        })
+    }
+}
+
+/// A fake device served by [`MockBackend`]
+#[derive(Debug, Clone)]
+pub struct MockDevice {
+    pub device: UsbmuxdDevice,
+    /// Lockdown values, as returned by `GetValue` with no key
+    pub values: plist::Dictionary,
+    /// Canned responses keyed by (plane, entry, class)
+    pub registries: Vec<(RegistryQuery, plist::Dictionary)>,
+    /// Other diagnostics keyed by domain, as answered to `All`
+    pub diagnostics: plist::Dictionary,
+    /// MobileGestalt values, of which only the requested keys are answered
+    pub gestalt: plist::Dictionary,
+    pub fault: Option<MockFault>,
+    /// How long the relay takes to answer
+    pub latency: Duration,
+    /// How long lockdown takes to answer
+    pub lockdown_latency: Duration,
+}
+
+/// The arguments of an `ioregistry` request
+pub type RegistryQuery = (Option<String>, Option<String>, Option<String>);
+
+impl MockDevice {
+    /// Creates a USB device with the given UDID and `DeviceName`
+    pub fn new(udid: impl Into<String>, device_id: u32, name: impl Into<String>) -> Self {
+        let udid = udid.into();
+        let mut values = plist::Dictionary::new();
+        values.insert("DeviceName".into(), name.into().into());
+        values.insert("UniqueDeviceID".into(), udid.clone().into());
+        Self {
+            device: UsbmuxdDevice {
+                connection_type: Connection::Usb,
+                udid,
+                device_id,
+            },
+            values,
+            registries: Vec::new(),
+            diagnostics: plist::Dictionary::new(),
+            gestalt: plist::Dictionary::new(),
+            fault: None,
+            latency: Duration::ZERO,
+            lockdown_latency: Duration::ZERO,
+        }
+    }
+
+    /// Sets a lockdown value
+    pub fn with_value(mut self, key: impl Into<String>, value: impl Into<plist::Value>) -> Self {
+        self.values.insert(key.into(), value.into());
+        self
+    }
+
+    /// Serves `registry` for exactly this query
+    pub fn with_ioregistry(
+        mut self,
+        plane: Option<&str>,
+        entry: Option<&str>,
+        class: Option<&str>,
+        registry: plist::Dictionary,
+    ) -> Self {
+        let query = (
+            plane.map(String::from),
+            entry.map(String::from),
+            class.map(String::from),
+        );
+        self.registries.push((query, registry));
+        self
+    }
+
+    /// Serves the dictionary in a plist file for exactly this query
+    pub fn with_ioregistry_fixture(
+        self,
+        plane: Option<&str>,
+        entry: Option<&str>,
+        class: Option<&str>,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, IdeviceError> {
+        let registry = plist::from_file(path)?;
+        Ok(self.with_ioregistry(plane, entry, class, registry))
+    }
+
+    /// Serves `values` for a diagnostics domain such as `GasGauge`
+    pub fn with_diagnostics(
+        mut self,
+        domain: impl Into<String>,
+        values: plist::Dictionary,
+    ) -> Self {
+        self.diagnostics.insert(domain.into(), values.into());
+        self
+    }
+
+    /// Sets a MobileGestalt value
+    pub fn with_gestalt(mut self, key: impl Into<String>, value: impl Into<plist::Value>) -> Self {
+        self.gestalt.insert(key.into(), value.into());
+        self
+    }
+
+    /// Delays every relay answer, like a relay busy walking a big tree
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Delays every lockdown answer, like a Wi-Fi device that has left the network
+    pub fn with_lockdown_latency(mut self, latency: Duration) -> Self {
+        self.lockdown_latency = latency;
+        self
+    }
+
+    /// Makes every request to this device fail
+    pub fn with_fault(mut self, fault: MockFault) -> Self {
+        self.fault = Some(fault);
+        self
+    }
+}
+
+/// How long a mock device is gone for while restarting
+const RESTART_TIME: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Default)]
+struct MockState {
+    devices: Vec<MockDevice>,
+    fault: Option<MockFault>,
+    /// Told about every device added or removed
+    listeners: Vec<UnboundedSender<()>>,
+    /// Every power action asked for, by UDID
+    power_requests: Vec<(String, PowerAction, PowerOptions)>,
+}
+
+impl MockState {
+    fn notify(&mut self) {
+        self.listeners.retain(|l| l.send(()).is_ok());
+    }
+}
+
+/// Backend that serves [`MockDevice`]s from memory
+///
+/// Clones share state, so a test can keep a handle and change devices or faults
+/// while the command loop owns the backend.
+#[derive(Debug, Clone, Default)]
+pub struct MockBackend {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockBackend {
+    pub fn new(devices: impl IntoIterator<Item = MockDevice>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MockState {
+                devices: devices.into_iter().collect(),
+                fault: None,
+                listeners: Vec::new(),
+                power_requests: Vec::new(),
+            })),
+        }
+    }
+
+    /// Attaches a device, as if plugged in
+    pub fn add_device(&self, device: MockDevice) {
+        let mut state = self.state.lock().unwrap();
+        state.devices.push(device);
+        state.notify();
+    }
+
+    /// Detaches a device, as if unplugged
+    pub fn remove_device(&self, udid: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.devices.retain(|d| d.device.udid != udid);
+        state.notify();
+    }
+
+    /// Swaps in new data for an attached device without unplugging it
+    pub fn update_device(&self, device: MockDevice) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(d) = state
+            .devices
+            .iter_mut()
+            .find(|d| d.device.udid == device.device.udid)
+        {
+            *d = device;
+        }
+    }
+
+    /// Makes listing devices fail
+    pub fn set_fault(&self, fault: Option<MockFault>) {
+        self.state.lock().unwrap().fault = fault;
+    }
+
+    /// Makes every request to one device fail
+    pub fn set_device_fault(&self, udid: &str, fault: Option<MockFault>) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(d) = state.devices.iter_mut().find(|d| d.device.udid == udid) {
+            d.fault = fault;
+        }
+    }
+
+    /// Power actions asked for so far, by UDID
+    pub fn power_requests(&self) -> Vec<(String, PowerAction, PowerOptions)> {
+        self.state.lock().unwrap().power_requests.clone()
+    }
+
+    fn find(&self, dev: &UsbmuxdDevice) -> Result<MockDevice, IdeviceError> {
+        let state = self.state.lock().unwrap();
+        match state.devices.iter().find(|d| d.device.udid == dev.udid) {
+            Some(d) => Ok(d.clone()),
+            None => Err(IdeviceError::DeviceNotFound),
+        }
+    }
+}
+
+impl RegistryBackend for MockBackend {
+    async fn list_devices(&self) -> Result<Vec<UsbmuxdDevice>, IdeviceError> {
+        let (fault, devices) = {
+            let state = self.state.lock().unwrap();
+            let devices = state.devices.iter().map(|d| d.device.clone()).collect();
+            (state.fault, devices)
+        };
+        // The lock is released first so a panic doesn't poison it
+        match fault.and_then(MockFault::to_error) {
+            Some(e) => Err(e),
+            None => Ok(devices),
+        }
+    }
+
+    async fn watch_devices(&self, changed: UnboundedSender<()>) -> Result<(), IdeviceError> {
+        self.state.lock().unwrap().listeners.push(changed.clone());
+        changed.closed().await;
+        Ok(())
+    }
+
+    async fn device_info(&self, dev: &UsbmuxdDevice) -> Result<plist::Dictionary, IdeviceError> {
+        let d = self.find(dev)?;
+        if !d.lockdown_latency.is_zero() {
+            tokio::time::sleep(d.lockdown_latency).await;
+        }
+        match d.fault {
+            Some(MockFault::RelayTimeout) | None => Ok(d.values),
+            Some(fault) => fault.to_error().map_or(Ok(d.values), Err),
+        }
+    }
+
+    async fn ioregistry(
+        &self,
+        dev: &UsbmuxdDevice,
+        plane: Option<String>,
+        entry: Option<String>,
+        class: Option<String>,
+    ) -> Result<Option<plist::Dictionary>, IdeviceError> {
+        let d = self.find(dev)?;
+        if !d.latency.is_zero() {
+            tokio::time::sleep(d.latency).await;
+        }
+        if let Some(fault) = d.fault {
+            return fault.to_error().map_or(Ok(None), Err);
+        }
+
+        let query = (plane, entry, class);
+        Ok(d.registries
+            .into_iter()
+            .find(|(q, _)| *q == query)
+            .map(|(_, r)| r))
+    }
+
+    async fn diagnostics(
+        &self,
+        dev: &UsbmuxdDevice,
+        query: DiagnosticsQuery,
+    ) -> Result<Option<plist::Dictionary>, IdeviceError> {
+        let d = self.find(dev)?;
+        if !d.latency.is_zero() {
+            tokio::time::sleep(d.latency).await;
+        }
+        if let Some(fault) = d.fault {
+            return fault.to_error().map_or(Ok(None), Err);
+        }
+
+        Ok(Some(match query {
+            DiagnosticsQuery::MobileGestalt(keys) => d
+                .gestalt
+                .into_iter()
+                .filter(|(k, _)| keys.contains(k))
+                .collect(),
+            DiagnosticsQuery::All => d.diagnostics,
+            query => d
+                .diagnostics
+                .into_iter()
+                .filter(|(k, _)| k == query.request())
+                .collect(),
+        }))
+    }
+
+    /// Restarting unplugs the device for a moment and shutting down unplugs it for good
+    async fn power(
+        &self,
+        dev: &UsbmuxdDevice,
+        action: PowerAction,
+        options: PowerOptions,
+    ) -> Result<(), IdeviceError> {
+        let d = self.find(dev)?;
+        if !d.latency.is_zero() {
+            tokio::time::sleep(d.latency).await;
+        }
+        if let Some(e) = d.fault.and_then(MockFault::to_error) {
+            return Err(e);
+        }
+        self.state
+            .lock()
+            .unwrap()
+            .power_requests
+            .push((d.device.udid.clone(), action, options));
+
+        if action.detaches() {
+            let backend = self.clone();
+            tokio::spawn(async move {
+                // After answering, as the relay does
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                backend.remove_device(&d.device.udid);
+                if action == PowerAction::Restart {
+                    tokio::time::sleep(RESTART_TIME).await;
+                    backend.add_device(d);
+                }
+            });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+// Jackson Coxson
+//! Device backends
+//!
+//! A backend is anything that can enumerate devices and answer lockdown and
+//! diagnostics relay queries for them. The GUI only talks to a backend through
+//! the command loop, so other tools can reuse the querying logic directly.
+
+use std::{future::Future, str::FromStr};
+
+use idevice::{IdeviceError, usbmuxd::UsbmuxdDevice};
+use tokio::sync::mpsc::UnboundedSender;
+
+#[cfg(feature = "mock")]
+pub mod mock;
+mod network;
+mod relay;
+mod usbmuxd;
+
+pub use network::{NetworkDiscovery, SERVICE_NAME};
+pub use usbmuxd::UsbmuxdBackend;
+
+/// A diagnostics relay request other than IORegistry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiagnosticsQuery {
+    /// Values for these MobileGestalt keys, which iOS 17.4 and later refuse
+    MobileGestalt(Vec<String>),
+    GasGauge,
+    WiFi,
+    NAND,
+    /// Every domain above except MobileGestalt
+    All,
+}
+
+impl DiagnosticsQuery {
+    /// The `Request` value the relay knows it by
+    pub fn request(&self) -> &'static str {
+        match self {
+            Self::MobileGestalt(_) => "MobileGestalt",
+            Self::GasGauge => "GasGauge",
+            Self::WiFi => "WiFi",
+            Self::NAND => "NAND",
+            Self::All => "All",
+        }
+    }
+}
+
+/// What the relay can be asked to do to a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerAction {
+    Restart,
+    Shutdown,
+    Sleep,
+}
+
+impl PowerAction {
+    pub const ALL: [Self; 3] = [Self::Restart, Self::Shutdown, Self::Sleep];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Restart => "Restart",
+            Self::Shutdown => "Shut Down",
+            Self::Sleep => "Sleep",
+        }
+    }
+
+    /// The `Request` value the relay knows it by
+    pub fn request(self) -> &'static str {
+        match self {
+            Self::Restart => "Restart",
+            Self::Shutdown => "Shutdown",
+            Self::Sleep => "Sleep",
+        }
+    }
+
+    /// Whether the device drops off usbmuxd afterwards
+    pub fn detaches(self) -> bool {
+        matches!(self, Self::Restart | Self::Shutdown)
+    }
+}
+
+impl FromStr for PowerAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "restart" | "reboot" => Ok(Self::Restart),
+            "shutdown" => Ok(Self::Shutdown),
+            "sleep" => Ok(Self::Sleep),
+            _ => Err(format!(
+                "Unknown action {s}, expected restart, shutdown or sleep"
+            )),
+        }
+    }
+}
+
+/// Options for restarting or shutting down, ignored by sleep
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PowerOptions {
+    /// Hold off until every connection to the device is closed
+    pub wait_for_disconnect: bool,
+    /// Show a notification on the device first
+    pub show_notification: bool,
+}
+
+/// Source of devices and their registries
+pub trait RegistryBackend: Send + Sync + 'static {
+    /// Lists the devices currently available to this backend
+    ///
+    /// # Errors
+    /// Returns `IdeviceError::Socket` if the backend itself can't be reached
+    fn list_devices(&self)
+    -> impl Future<Output = Result<Vec<UsbmuxdDevice>, IdeviceError>> + Send;
+
+    /// Sends on `changed` whenever a device is attached or detached
+    ///
+    /// Runs until `changed` is closed, returning an error if the subscription
+    /// can't be made or is lost.
+    fn watch_devices(
+        &self,
+        changed: UnboundedSender<()>,
+    ) -> impl Future<Output = Result<(), IdeviceError>> + Send;
+
+    /// Gets all lockdown values for a device
+    fn device_info(
+        &self,
+        dev: &UsbmuxdDevice,
+    ) -> impl Future<Output = Result<plist::Dictionary, IdeviceError>> + Send;
+
+    /// Queries the IORegistry of a device
+    ///
+    /// # Arguments
+    /// * `plane` - The plane to request the tree as
+    /// * `entry` - The entry to get
+    /// * `class` - The class to filter by
+    fn ioregistry(
+        &self,
+        dev: &UsbmuxdDevice,
+        plane: Option<String>,
+        entry: Option<String>,
+        class: Option<String>,
+    ) -> impl Future<Output = Result<Option<plist::Dictionary>, IdeviceError>> + Send;
+
+    /// Makes any other diagnostics relay request
+    ///
+    /// Returns the relay's `Diagnostics` dictionary, keyed by domain. For
+    /// MobileGestalt it is the requested keys instead.
+    fn diagnostics(
+        &self,
+        dev: &UsbmuxdDevice,
+        query: DiagnosticsQuery,
+    ) -> impl Future<Output = Result<Option<plist::Dictionary>, IdeviceError>> + Send;
+
+    /// Restarts, shuts down or sleeps a device, returning once the relay has agreed to
+    fn power(
+        &self,
+        dev: &UsbmuxdDevice,
+        action: PowerAction,
+        options: PowerOptions,
+    ) -> impl Future<Output = Result<(), IdeviceError>> + Send;
+}
@@ -0,0 +1,102 @@
+// Jackson Coxson
+//! Finding Wi-Fi paired devices over mDNS
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use futures_util::{StreamExt, pin_mut};
+use log::{debug, warn};
+
+/// Service paired devices advertise while on Wi-Fi
+pub const SERVICE_NAME: &str = "_apple-mobdev2._tcp.local";
+
+/// How often to ask the network for devices
+const QUERY_INTERVAL: Duration = Duration::from_secs(15);
+/// Devices not heard from in this long are assumed gone
+const EXPIRY: Duration = Duration::from_secs(45);
+
+/// Where a device was heard and when, or `None` for ones added by hand
+type Sighting = (IpAddr, Option<Instant>);
+
+/// Devices heard on the network, keyed by Wi-Fi MAC address
+#[derive(Debug, Clone, Default)]
+pub struct NetworkDiscovery {
+    seen: Arc<Mutex<HashMap<String, Sighting>>>,
+}
+
+impl NetworkDiscovery {
+    /// Starts browsing in the background. Must be called from within a tokio runtime.
+    pub fn start() -> Self {
+        let discovery = Self::default();
+        let d = discovery.clone();
+        tokio::spawn(async move {
+            let stream = match mdns::discover::all(SERVICE_NAME, QUERY_INTERVAL) {
+                Ok(s) => s.listen(),
+                Err(e) => {
+                    warn!("Failed to start mDNS discovery: {e:?}");
+                    return;
+                }
+            };
+            pin_mut!(stream);
+            while let Some(response) = stream.next().await {
+                let response = match response {
+                    Ok(r) => r,
+                    Err(e) => {
+                        debug!("Bad mDNS response: {e:?}");
+                        continue;
+                    }
+                };
+                // Prefer IPv4, link-local IPv6 needs a scope we don't get here
+                let Some(ip) = response
+                    .records()
+                    .find_map(|r| match r.kind {
+                        mdns::RecordKind::A(a) => Some(IpAddr::from(a)),
+                        _ => None,
+                    })
+                    .or_else(|| response.ip_addr())
+                else {
+                    continue;
+                };
+                for r in response.records() {
+                    if let mdns::RecordKind::PTR(instance) = &r.kind
+                        && let Some(mac) = mac_from_instance(instance)
+                    {
+                        d.seen
+                            .lock()
+                            .unwrap()
+                            .insert(mac, (ip, Some(Instant::now())));
+                    }
+                }
+            }
+        });
+        discovery
+    }
+
+    /// Records a device by hand, e.g. one on a network mDNS doesn't reach
+    pub fn insert(&self, mac: impl Into<String>, ip: IpAddr) {
+        self.seen
+            .lock()
+            .unwrap()
+            .insert(mac.into().to_lowercase(), (ip, None));
+    }
+
+    /// Devices heard from recently, as (Wi-Fi MAC address, IP address)
+    pub fn devices(&self) -> Vec<(String, IpAddr)> {
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, (_, at)| at.is_none_or(|at| at.elapsed() < EXPIRY));
+        seen.iter()
+            .map(|(mac, (ip, _))| (mac.clone(), *ip))
+            .collect()
+    }
+}
+
+/// Instances are named `<wifi mac>@<address>._apple-mobdev2._tcp.local`
+fn mac_from_instance(instance: &str) -> Option<String> {
+    let (mac, _) = instance.split_once('@')?;
+    let valid = mac.split(':').count() == 6 && mac.split(':').all(|x| x.len() <= 2);
+    valid.then(|| mac.to_lowercase())
+}
@@ -0,0 +1,99 @@
+// Jackson Coxson
+//! Diagnostics relay requests idevice has no method for
+//!
+//! Messages are XML plists, each preceded by its length as a big endian u32.
+
+use idevice::{Idevice, IdeviceError};
+
+use super::{DiagnosticsQuery, PowerAction, PowerOptions};
+
+/// What a device answers MobileGestalt with since iOS 17.4
+const GESTALT_DEPRECATED: &str = "MobileGestaltDeprecated";
+
+/// Sends a diagnostics request, returning the `Diagnostics` dictionary it answers with
+///
+/// For MobileGestalt that is the requested keys and their values.
+pub async fn diagnostics(
+    idevice: &mut Idevice,
+    query: &DiagnosticsQuery,
+) -> Result<Option<plist::Dictionary>, IdeviceError> {
+    let mut req = plist::Dictionary::new();
+    req.insert("Request".into(), query.request().into());
+    if let DiagnosticsQuery::MobileGestalt(keys) = query {
+        let keys = keys
+            .iter()
+            .map(|k| plist::Value::from(k.as_str()))
+            .collect();
+        req.insert("MobileGestaltKeys".into(), plist::Value::Array(keys));
+    }
+    let res = request(idevice, req).await?;
+
+    match res.get("Status").and_then(|x| x.as_string()) {
+        Some("Success") => {}
+        _ => return Err(IdeviceError::UnexpectedResponse),
+    }
+    let Some(plist::Value::Dictionary(diagnostics)) = res.get("Diagnostics") else {
+        return Ok(None);
+    };
+    if !matches!(query, DiagnosticsQuery::MobileGestalt(_)) {
+        return Ok(Some(diagnostics.clone()));
+    }
+
+    let Some(plist::Value::Dictionary(gestalt)) = diagnostics.get("MobileGestalt") else {
+        return Ok(None);
+    };
+    let mut gestalt = gestalt.clone();
+    match gestalt
+        .remove("Status")
+        .as_ref()
+        .and_then(|x| x.as_string())
+    {
+        Some(GESTALT_DEPRECATED) => Err(IdeviceError::UnknownErrorType(
+            GESTALT_DEPRECATED.to_string(),
+        )),
+        _ => Ok(Some(gestalt)),
+    }
+}
+
+/// Asks the relay to restart, shut down or sleep the device
+pub async fn power(
+    idevice: &mut Idevice,
+    action: PowerAction,
+    options: PowerOptions,
+) -> Result<(), IdeviceError> {
+    let mut req = plist::Dictionary::new();
+    req.insert("Request".into(), action.request().into());
+    if action.detaches() {
+        req.insert(
+            "WaitForDisconnect".into(),
+            options.wait_for_disconnect.into(),
+        );
+        req.insert("DisplayPass".into(), options.show_notification.into());
+        req.insert("DisplayFail".into(), false.into());
+    }
+    let res = request(idevice, req).await?;
+    match res.get("Status").and_then(|x| x.as_string()) {
+        Some("Success") => Ok(()),
+        _ => Err(IdeviceError::UnexpectedResponse),
+    }
+}
+
+async fn request(
+    idevice: &mut Idevice,
+    req: plist::Dictionary,
+) -> Result<plist::Dictionary, IdeviceError> {
+    let mut body = Vec::new();
+    plist::to_writer_xml(&mut body, &req)?;
+    let mut packet = Vec::with_capacity(body.len() + 4);
+    packet.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    packet.extend_from_slice(&body);
+    idevice.send_raw(&packet).await?;
+
+    let len = idevice.read_raw(4).await?;
+    let len = u32::from_be_bytes(len[..4].try_into().unwrap());
+    let res: plist::Dictionary = plist::from_bytes(&idevice.read_raw(len as usize).await?)?;
+    if let Some(e) = res.get("Error").and_then(|x| x.as_string()) {
+        return Err(IdeviceError::UnknownErrorType(e.to_string()));
+    }
+    Ok(res)
+}
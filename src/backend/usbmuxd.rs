@@ -0,0 +1,259 @@
+// Jackson Coxson
+
+use std::{future::Future, pin::Pin};
+
+use idevice::{
+    Idevice, IdeviceError, IdeviceService,
+    diagnostics_relay::DiagnosticsRelayClient,
+    lockdown::LockdownClient,
+    pairing_file::PairingFile,
+    provider::{IdeviceProvider, TcpProvider, UsbmuxdProvider},
+    usbmuxd::{Connection, UsbmuxdAddr, UsbmuxdDevice},
+};
+use log::{debug, warn};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::mpsc::UnboundedSender,
+};
+
+use super::{
+    DiagnosticsQuery, PowerAction, PowerOptions, RegistryBackend, network::NetworkDiscovery, relay,
+};
+use crate::pairing::PairingStore;
+
+/// Backend that reaches devices through the host's usbmuxd
+///
+/// With [`UsbmuxdBackend::with_network`] it also lists Wi-Fi devices found over
+/// mDNS that have an imported pairing file, and talks to them directly over TCP.
+#[derive(Debug, Clone, Default)]
+pub struct UsbmuxdBackend {
+    addr: UsbmuxdAddr,
+    store: Option<PairingStore>,
+    discovery: Option<NetworkDiscovery>,
+}
+
+impl UsbmuxdBackend {
+    /// Creates a backend that talks to usbmuxd at `addr`
+    pub fn new(addr: UsbmuxdAddr) -> Self {
+        Self {
+            addr,
+            store: None,
+            discovery: None,
+        }
+    }
+
+    /// Falls back to records in `store` for devices usbmuxd has no pairing record for
+    pub fn with_pairing_store(mut self, store: PairingStore) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Also lists devices found by `discovery` that have a record in `store`
+    pub fn with_network(self, discovery: NetworkDiscovery, store: PairingStore) -> Self {
+        let mut s = self.with_pairing_store(store);
+        s.discovery = Some(discovery);
+        s
+    }
+
+    fn provider(&self, dev: &UsbmuxdDevice, label: &str) -> Box<dyn IdeviceProvider> {
+        let Some(store) = &self.store else {
+            return Box::new(dev.to_provider(self.addr.clone(), label));
+        };
+        if let Connection::Network(ip) = dev.connection_type
+            && let Some(pairing_file) = store.get(&dev.udid)
+        {
+            return Box::new(TcpProvider {
+                addr: ip,
+                pairing_file,
+                label: label.to_string(),
+            });
+        }
+        Box::new(StoreFallbackProvider {
+            inner: dev.to_provider(self.addr.clone(), label),
+            store: store.clone(),
+        })
+    }
+
+    /// Wi-Fi devices we have a pairing file for
+    fn network_devices(&self) -> Vec<UsbmuxdDevice> {
+        let (Some(discovery), Some(store)) = (&self.discovery, &self.store) else {
+            return Vec::new();
+        };
+        discovery
+            .devices()
+            .into_iter()
+            .filter_map(|(mac, ip)| {
+                let record = store.find_by_mac(&mac)?;
+                Some(UsbmuxdDevice {
+                    connection_type: Connection::Network(ip),
+                    udid: record.udid,
+                    device_id: 0,
+                })
+            })
+            .collect()
+    }
+}
+
+impl RegistryBackend for UsbmuxdBackend {
+    async fn list_devices(&self) -> Result<Vec<UsbmuxdDevice>, IdeviceError> {
+        let network = self.network_devices();
+        let mut devs = match self.addr.connect(0).await {
+            Ok(mut uc) => uc.get_devices().await?,
+            // Wi-Fi devices are still reachable without usbmuxd
+            Err(e) if !network.is_empty() => {
+                warn!("Failed to connect to usbmuxd, only listing Wi-Fi devices: {e:?}");
+                Vec::new()
+            }
+            Err(e) => return Err(e),
+        };
+
+        // usbmuxd may already know a device over Wi-Fi too
+        for dev in network {
+            if !devs
+                .iter()
+                .any(|d| d.udid == dev.udid && matches!(d.connection_type, Connection::Network(_)))
+            {
+                devs.push(dev);
+            }
+        }
+        Ok(devs)
+    }
+
+    /// idevice has no `Listen`, so this speaks the muxer protocol itself
+    ///
+    /// Wi-Fi devices found over mDNS aren't reported, only those usbmuxd knows.
+    async fn watch_devices(&self, changed: UnboundedSender<()>) -> Result<(), IdeviceError> {
+        let mut socket = self.addr.to_socket().await?;
+        let mut req = plist::Dictionary::new();
+        req.insert("MessageType".into(), "Listen".into());
+        req.insert("ProgName".into(), "ioreg_explorer".into());
+        req.insert("ClientVersionString".into(), "ioreg_explorer".into());
+        write_mux_packet(&mut socket, req).await?;
+        let res = read_mux_packet(&mut socket).await?;
+        if res.get("Number").and_then(|x| x.as_unsigned_integer()) != Some(0) {
+            return Err(IdeviceError::UnexpectedResponse);
+        }
+
+        loop {
+            let msg = tokio::select! {
+                msg = read_mux_packet(&mut socket) => msg?,
+                _ = changed.closed() => return Ok(()),
+            };
+            match msg.get("MessageType").and_then(|x| x.as_string()) {
+                Some("Attached" | "Detached") => {
+                    if changed.send(()).is_err() {
+                        return Ok(());
+                    }
+                }
+                other => debug!("Ignoring usbmuxd message {other:?}"),
+            }
+        }
+    }
+
+    async fn device_info(&self, dev: &UsbmuxdDevice) -> Result<plist::Dictionary, IdeviceError> {
+        let p = self.provider(dev, "idevice_pair");
+        let mut lc = LockdownClient::connect(&*p).await?;
+        lc.get_all_values(None).await
+    }
+
+    async fn ioregistry(
+        &self,
+        dev: &UsbmuxdDevice,
+        plane: Option<String>,
+        entry: Option<String>,
+        class: Option<String>,
+    ) -> Result<Option<plist::Dictionary>, IdeviceError> {
+        let p = self.provider(dev, "ioreg_explorer");
+        let mut dc = DiagnosticsRelayClient::connect(&*p).await?;
+        dc.ioregistry(plane, entry, class).await
+    }
+
+    async fn diagnostics(
+        &self,
+        dev: &UsbmuxdDevice,
+        query: DiagnosticsQuery,
+    ) -> Result<Option<plist::Dictionary>, IdeviceError> {
+        let p = self.provider(dev, "ioreg_explorer");
+        let mut dc = DiagnosticsRelayClient::connect(&*p).await?;
+        relay::diagnostics(&mut dc.idevice, &query).await
+    }
+
+    async fn power(
+        &self,
+        dev: &UsbmuxdDevice,
+        action: PowerAction,
+        options: PowerOptions,
+    ) -> Result<(), IdeviceError> {
+        let p = self.provider(dev, "ioreg_explorer");
+        let mut dc = DiagnosticsRelayClient::connect(&*p).await?;
+        relay::power(&mut dc.idevice, action, options).await
+    }
+}
+
+/// Writes an XML plist message: a 16 byte header of length, version 1,
+/// message type 8 and tag, all little endian
+async fn write_mux_packet(
+    socket: &mut (impl AsyncWrite + Unpin),
+    req: plist::Dictionary,
+) -> Result<(), IdeviceError> {
+    let mut body = Vec::new();
+    plist::to_writer_xml(&mut body, &req)?;
+    let mut packet = Vec::with_capacity(body.len() + 16);
+    for field in [body.len() as u32 + 16, 1, 8, 1] {
+        packet.extend_from_slice(&field.to_le_bytes());
+    }
+    packet.extend_from_slice(&body);
+    socket.write_all(&packet).await?;
+    Ok(())
+}
+
+async fn read_mux_packet(
+    socket: &mut (impl AsyncRead + Unpin),
+) -> Result<plist::Dictionary, IdeviceError> {
+    let mut header = [0u8; 16];
+    socket.read_exact(&mut header).await?;
+    let size = u32::from_le_bytes(header[..4].try_into().unwrap());
+    let mut body = vec![0; size.saturating_sub(16) as usize];
+    socket.read_exact(&mut body).await?;
+    Ok(plist::from_bytes(&body)?)
+}
+
+/// Connects through usbmuxd, but uses an imported pairing file if usbmuxd has none
+#[derive(Debug)]
+struct StoreFallbackProvider {
+    inner: UsbmuxdProvider,
+    store: PairingStore,
+}
+
+impl IdeviceProvider for StoreFallbackProvider {
+    fn connect(
+        &self,
+        port: u16,
+    ) -> Pin<Box<dyn Future<Output = Result<Idevice, IdeviceError>> + Send>> {
+        self.inner.connect(port)
+    }
+
+    fn label(&self) -> &str {
+        self.inner.label()
+    }
+
+    fn get_pairing_file(
+        &self,
+    ) -> Pin<Box<dyn Future<Output = Result<PairingFile, IdeviceError>> + Send>> {
+        let from_usbmuxd = self.inner.get_pairing_file();
+        let store = self.store.clone();
+        let udid = self.inner.udid.clone();
+        Box::pin(async move {
+            match from_usbmuxd.await {
+                Ok(p) => Ok(p),
+                Err(e) => match store.get(&udid) {
+                    Some(p) => {
+                        debug!("usbmuxd has no pairing record for {udid}, using the imported one");
+                        Ok(p)
+                    }
+                    None => Err(e),
+                },
+            }
+        })
+    }
+}
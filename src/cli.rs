@@ -0,0 +1,353 @@
+// Jackson Coxson
+//! Headless interface, for machines without a display
+
+use std::{io::Write, path::PathBuf, time::Duration};
+
+use clap::{Args, Parser, Subcommand};
+use idevice::usbmuxd::{UsbmuxdAddr, UsbmuxdDevice};
+use tokio::sync::mpsc::unbounded_channel;
+
+use ioreg_explorer::{
+    backend::{PowerAction, PowerOptions, RegistryBackend, UsbmuxdBackend},
+    commands::{DeviceKey, connection_label, device_info_fields, explain_error},
+    diff::{ChangeKind, Snapshot, diff},
+    export::{DataEncoding, ExportFormat, export},
+    model::Registry,
+    pairing::PairingStore,
+    power::PowerProgress,
+    registry::format_value,
+};
+
+/// Explore the IORegistry of iOS devices. Opens the GUI when run without a command.
+#[derive(Parser)]
+#[command(name = "ioreg_explorer", version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    #[command(flatten)]
+    Device(DeviceCommand),
+    /// Compare two saved registry dumps
+    Diff {
+        /// The dump from before
+        old: PathBuf,
+        /// The dump from after
+        new: PathBuf,
+    },
+    /// Manage imported pairing files
+    Pairing {
+        #[command(subcommand)]
+        action: PairingCommand,
+    },
+}
+
+/// Commands that go through usbmuxd to a device
+#[derive(Subcommand)]
+pub enum DeviceCommand {
+    /// List connected devices
+    Devices,
+    /// Print information about a device
+    Info {
+        #[command(flatten)]
+        device: DeviceArgs,
+    },
+    /// Query the IORegistry of a device
+    Query {
+        #[command(flatten)]
+        device: DeviceArgs,
+        /// The plane to request the tree as, e.g. IOService
+        #[arg(long)]
+        plane: Option<String>,
+        /// The entry to get
+        #[arg(long)]
+        entry: Option<String>,
+        /// The class to filter by, e.g. IOPMPowerSource
+        #[arg(long)]
+        class: Option<String>,
+        /// Write the result to a file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// xml, binary, json or yaml. Prints a readable dump if omitted.
+        #[arg(short, long)]
+        format: Option<ExportFormat>,
+        /// How to write Data values in JSON and YAML: base64 or hex
+        #[arg(long, default_value = "base64")]
+        data_encoding: DataEncoding,
+    },
+    /// Restart, shut down or sleep a device
+    Power {
+        #[command(flatten)]
+        device: DeviceArgs,
+        /// restart, shutdown or sleep
+        action: PowerAction,
+        /// Hold off until every connection to the device is closed
+        #[arg(long)]
+        wait_for_disconnect: bool,
+        /// Show a notification on the device first
+        #[arg(long)]
+        notify: bool,
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+        /// Wait for the device to go away, and to come back after a restart
+        #[arg(long)]
+        follow: bool,
+        /// How many seconds --follow waits before giving up
+        #[arg(long, default_value_t = 300)]
+        timeout: u64,
+    },
+}
+
+/// Pairing files are used when usbmuxd has no record for a device, and to
+/// connect to devices over Wi-Fi
+#[derive(Subcommand)]
+pub enum PairingCommand {
+    /// Copy a pairing file into the store
+    Import {
+        /// The `.plist` pairing file
+        path: PathBuf,
+        /// UDID of the device it belongs to, if the file doesn't say
+        #[arg(short, long)]
+        udid: Option<String>,
+    },
+    /// List imported pairing files
+    List,
+    /// Remove the pairing file for a device
+    Delete {
+        /// UDID of the device
+        udid: String,
+    },
+}
+
+#[derive(Args)]
+pub struct DeviceArgs {
+    /// UDID of the device to use. May be omitted when only one device is connected.
+    #[arg(short, long)]
+    udid: Option<String>,
+}
+
+pub async fn run(command: Command) -> Result<(), String> {
+    match command {
+        // Pairing files are only a fallback for these
+        Command::Device(command) => run_device(command, PairingStore::open_default().ok()).await,
+        Command::Diff { old, new } => diff_files(&old, &new),
+        Command::Pairing { action } => pairing(&PairingStore::open_default()?, action),
+    }
+}
+
+/// Runs a command against the usbmuxd named by `USBMUXD_SOCKET_ADDRESS`, or the default one
+async fn run_device(command: DeviceCommand, store: Option<PairingStore>) -> Result<(), String> {
+    let addr =
+        UsbmuxdAddr::from_env_var().map_err(|e| format!("Invalid USBMUXD_SOCKET_ADDRESS: {e}"))?;
+    let mut backend = UsbmuxdBackend::new(addr);
+    if let Some(store) = store {
+        backend = backend.with_pairing_store(store);
+    }
+
+    match command {
+        DeviceCommand::Devices => {
+            let devs = list_devices(&backend).await?;
+            for dev in devs {
+                let name = match backend.device_info(&dev).await {
+                    Ok(values) => values
+                        .get("DeviceName")
+                        .and_then(|x| x.as_string())
+                        .unwrap_or_default()
+                        .to_string(),
+                    Err(e) => format!("<lockdown failed: {e}>"),
+                };
+                println!(
+                    "{}\t{}\t{name}",
+                    dev.udid,
+                    connection_label(&dev.connection_type)
+                );
+            }
+        }
+        DeviceCommand::Info { device } => {
+            let dev = pick_device(&backend, &device).await?;
+            let values = backend
+                .device_info(&dev)
+                .await
+                .map_err(|e| format!("Failed to get lockdown values: {}", explain_error(&e)))?;
+            for (key, value) in device_info_fields(&values) {
+                println!("{key}: {value}");
+            }
+        }
+        DeviceCommand::Query {
+            device,
+            plane,
+            entry,
+            class,
+            output,
+            format,
+            data_encoding,
+        } => {
+            let dev = pick_device(&backend, &device).await?;
+            let res = backend
+                .ioregistry(&dev, plane.clone(), entry, class)
+                .await
+                .map_err(|e| format!("Failed to get IO registry: {}", explain_error(&e)))?
+                .ok_or("The device returned no registry for this query")?;
+
+            let bytes = match format {
+                Some(f) => export(&Registry::new(res, plane.as_deref()), f, data_encoding)
+                    .map_err(|e| format!("Failed to serialize the registry: {e}"))?,
+                None => format!("{}\n", idevice::pretty_print_dictionary(&res)).into_bytes(),
+            };
+            match output {
+                Some(p) => std::fs::write(&p, bytes)
+                    .map_err(|e| format!("Failed to write {}: {e}", p.display()))?,
+                None => std::io::stdout()
+                    .write_all(&bytes)
+                    .map_err(|e| format!("Failed to write to stdout: {e}"))?,
+            }
+        }
+        DeviceCommand::Power {
+            device,
+            action,
+            wait_for_disconnect,
+            notify,
+            yes,
+            follow,
+            timeout,
+        } => {
+            let dev = pick_device(&backend, &device).await?;
+            if !yes && !confirm(&format!("{} {}?", action.name(), dev.udid))? {
+                return Err("Cancelled".to_string());
+            }
+            let options = PowerOptions {
+                wait_for_disconnect,
+                show_notification: notify,
+            };
+            let mut progress = PowerProgress::new(&dev.udid, action);
+            backend
+                .power(&dev, action, options)
+                .await
+                .map_err(|e| format!("Failed to {}: {}", action.request(), explain_error(&e)))?;
+            progress.sent();
+            println!("{} sent to {}", action.name(), dev.udid);
+            if follow {
+                follow_power(&backend, progress, Duration::from_secs(timeout)).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Asks on stderr, reading the answer from stdin
+fn confirm(question: &str) -> Result<bool, String> {
+    eprint!("{question} [y/N] ");
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .map_err(|e| format!("Failed to read the answer: {e}"))?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// How long --follow waits before listening again when usbmuxd goes away
+const FOLLOW_RETRY: Duration = Duration::from_millis(500);
+
+/// Prints each stage of a power action until it finishes or `timeout` passes
+///
+/// The device list is checked whenever usbmuxd reports a device attaching or
+/// detaching. usbmuxd reports every attached device on subscribing, so the
+/// list is also checked once the subscription is made.
+async fn follow_power(
+    backend: &UsbmuxdBackend,
+    mut progress: PowerProgress,
+    timeout: Duration,
+) -> Result<(), String> {
+    let (changed, mut events) = unbounded_channel();
+    let watch = backend.watch_devices(changed.clone());
+    tokio::pin!(watch);
+    let deadline = tokio::time::sleep(timeout.saturating_sub(progress.elapsed()));
+    tokio::pin!(deadline);
+
+    while !progress.is_finished() {
+        tokio::select! {
+            Some(()) = events.recv() => {
+                // One plug often comes as several events
+                while events.try_recv().is_ok() {}
+                let Ok(devs) = backend.list_devices().await else {
+                    continue;
+                };
+                if progress.update(devs.iter().any(|d| d.udid == progress.udid)) {
+                    println!("{}", progress.describe());
+                }
+            }
+            // usbmuxd may be briefly unreachable while the device re-enumerates
+            _ = &mut watch => {
+                tokio::time::sleep(FOLLOW_RETRY).await;
+                watch.set(backend.watch_devices(changed.clone()));
+            }
+            () = &mut deadline => {
+                return Err(format!("Gave up after {}s", timeout.as_secs()));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn diff_files(old: &std::path::Path, new: &std::path::Path) -> Result<(), String> {
+    let old = Snapshot::from_file(old)?;
+    let new = Snapshot::from_file(new)?;
+    let value = |v: &Option<plist::Value>| v.as_ref().map(format_value).unwrap_or_default();
+    for c in diff(&old.registry, &new.registry) {
+        match c.kind {
+            ChangeKind::Added => println!("+ {} = {}", c.path_string(), value(&c.new)),
+            ChangeKind::Removed => println!("- {} = {}", c.path_string(), value(&c.old)),
+            ChangeKind::Changed => println!(
+                "~ {}: {} -> {}",
+                c.path_string(),
+                value(&c.old),
+                value(&c.new)
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn pairing(store: &PairingStore, action: PairingCommand) -> Result<(), String> {
+    match action {
+        PairingCommand::Import { path, udid } => {
+            let udid = store.import(&path, udid.as_deref())?;
+            println!("Imported the pairing file for {udid}");
+        }
+        PairingCommand::List => {
+            for p in store.list() {
+                println!("{}\t{}\t{}", p.udid, p.wifi_mac_address, p.path.display());
+            }
+        }
+        PairingCommand::Delete { udid } => store.delete(&udid)?,
+    }
+    Ok(())
+}
+
+async fn list_devices(backend: &UsbmuxdBackend) -> Result<Vec<UsbmuxdDevice>, String> {
+    backend
+        .list_devices()
+        .await
+        .map_err(|e| format!("Failed to get list of connected devices from usbmuxd! {e}"))
+}
+
+async fn pick_device(backend: &UsbmuxdBackend, args: &DeviceArgs) -> Result<UsbmuxdDevice, String> {
+    let mut devs = list_devices(backend).await?;
+    if let Some(udid) = &args.udid {
+        devs.retain(|d| &d.udid == udid);
+        if devs.is_empty() {
+            return Err(format!("No device with UDID {udid} is connected"));
+        }
+    }
+    // A device may be connected more than once, prefer USB to the network
+    devs.sort_by_key(DeviceKey::of);
+    match devs.first() {
+        None => Err("No devices connected! Plug one in via USB.".to_string()),
+        Some(d) if devs.iter().any(|x| x.udid != d.udid) => {
+            Err("Multiple devices connected, pick one with --udid".to_string())
+        }
+        Some(_) => Ok(devs.remove(0)),
+    }
+}
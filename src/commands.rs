@@ -0,0 +1,509 @@
+// Jackson Coxson
+//! Messages between the GUI and the idevice task, and the task itself
+
+use std::{
+    collections::BTreeMap, future::Future, panic::AssertUnwindSafe, sync::Arc, time::Duration,
+};
+
+use futures_util::FutureExt;
+
+use idevice::{
+    IdeviceError,
+    usbmuxd::{Connection, UsbmuxdDevice},
+};
+use log::{debug, error};
+use tokio::{
+    sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel},
+    task::JoinHandle,
+};
+
+use crate::{
+    backend::{DiagnosticsQuery, PowerAction, PowerOptions, RegistryBackend},
+    registry::registry_planes,
+};
+
+/// Tags a relay query so its answer can be matched up with it
+pub type RequestId = u64;
+
+/// How a device is reached, without the details that may change while it stays connected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ConnectionKind {
+    Usb,
+    Network,
+    Unknown,
+}
+
+/// Identifies one connection to a device
+///
+/// A device plugged in over USB and reachable over Wi-Fi is listed once for each.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DeviceKey {
+    pub udid: String,
+    pub connection: ConnectionKind,
+}
+
+impl DeviceKey {
+    pub fn of(dev: &UsbmuxdDevice) -> Self {
+        let connection = match dev.connection_type {
+            Connection::Usb => ConnectionKind::Usb,
+            Connection::Network(_) => ConnectionKind::Network,
+            Connection::Unknown(_) => ConnectionKind::Unknown,
+        };
+        Self {
+            udid: dev.udid.clone(),
+            connection,
+        }
+    }
+}
+
+/// A device connection and the name to show it by
+#[derive(Debug, Clone)]
+pub struct ListedDevice {
+    /// `DeviceName`, or the UDID if the device didn't give one
+    pub name: String,
+    pub device: UsbmuxdDevice,
+}
+
+impl ListedDevice {
+    /// The name with how the device is connected, e.g. `iPhone (USB)`
+    pub fn label(&self) -> String {
+        format!(
+            "{} ({})",
+            self.name,
+            connection_label(&self.device.connection_type)
+        )
+    }
+}
+
+/// Messages sent from the idevice task to the GUI
+pub enum GuiCommands {
+    NoUsbmuxd(IdeviceError),
+    GetDevicesFailure(IdeviceError),
+    Devices(BTreeMap<DeviceKey, ListedDevice>),
+    /// A device usbmuxd lists but lockdown refused, sent after the list leaving it out
+    DeviceUnreachable(String, IdeviceError),
+    DeviceInfo(Vec<(String, String)>),
+    DeviceInfoFailure(IdeviceError),
+    /// The answer to an IORegistry or other diagnostics query
+    IORegistry(RequestId, Option<plist::Dictionary>),
+    IORegistryFailure(RequestId, IdeviceError),
+    /// The planes a device's registry has, by UDID
+    Planes(String, Vec<String>),
+    /// The relay agreed to a power action for the device with this UDID
+    PowerSent(String),
+    PowerFailure(String, IdeviceError),
+    /// The idevice task panicked and was started again. Requests in flight were lost.
+    BackendRestarted(String),
+}
+
+/// Requests sent from the GUI to the idevice task
+pub enum IdeviceCommands {
+    GetDevices,
+    GetDeviceInfo(UsbmuxdDevice),
+    /// Reads the planes from the root entry. Nothing is sent back if that fails.
+    GetPlanes(UsbmuxdDevice),
+    /// Supersedes any query still in flight, which is cancelled
+    IORegistsry(
+        RequestId,
+        (
+            UsbmuxdDevice,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+        ),
+    ),
+    /// Like `IORegistsry`, answered with the same messages
+    Diagnostics(RequestId, (UsbmuxdDevice, DiagnosticsQuery)),
+    /// Restarts, shuts down or sleeps a device. Follow it through the device list.
+    Power(UsbmuxdDevice, PowerAction, PowerOptions),
+}
+
+/// Fields shown next to the device selector, in display order
+const DEVICE_INFO_FIELDS: [(&str, &str); 5] = [
+    ("Device Name", "DeviceName"),
+    ("Model", "ProductType"),
+    ("iOS Version", "ProductVersion"),
+    ("Build Number", "BuildVersion"),
+    ("UDID", "UniqueDeviceID"),
+];
+
+/// Picks the displayed fields out of a device's lockdown values
+pub fn device_info_fields(values: &plist::Dictionary) -> Vec<(String, String)> {
+    let mut device_info = Vec::with_capacity(DEVICE_INFO_FIELDS.len());
+    for (display_name, key) in DEVICE_INFO_FIELDS.iter() {
+        if let Some(plist::Value::String(value)) = values.get(key) {
+            device_info.push((display_name.to_string(), value.clone()));
+        }
+    }
+    device_info
+}
+
+/// How a device is connected, for showing next to its name
+pub fn connection_label(connection: &Connection) -> String {
+    match connection {
+        Connection::Usb => "USB".to_string(),
+        Connection::Network(ip) => format!("Network {ip}"),
+        Connection::Unknown(s) => format!("Unknown {s}"),
+    }
+}
+
+/// What an error means for the user, and what they can do about it
+pub fn explain_error(e: &IdeviceError) -> String {
+    match e {
+        IdeviceError::Socket(e) if e.kind() == std::io::ErrorKind::TimedOut => {
+            "The device took too long to answer. It may be busy or asleep.".to_string()
+        }
+        IdeviceError::Socket(e) => {
+            format!("Couldn't talk to the device ({e}). Check that it's still connected.")
+        }
+        IdeviceError::DeviceLocked => {
+            "Device locked. Unlock it with its passcode, then retry.".to_string()
+        }
+        IdeviceError::InvalidHostID => "Pairing required. The device doesn't trust this \
+            computer: unlock it and tap Trust, or import a pairing file."
+            .to_string(),
+        // Without idevice's pair feature these come through by name
+        IdeviceError::UnknownErrorType(t) if t == "PairingDialogResponsePending" => {
+            "Trust dialog pending. Unlock the device and tap Trust.".to_string()
+        }
+        IdeviceError::UnknownErrorType(t) if t == "UserDeniedPairing" => {
+            "Trust was denied on the device. Unplug it, plug it back in and tap Trust.".to_string()
+        }
+        IdeviceError::UnknownErrorType(t) if t == "PasswordProtected" => {
+            "Device locked. Unlock it with its passcode, then retry.".to_string()
+        }
+        // What idevice returns when usbmuxd has no pair record
+        IdeviceError::UnexpectedResponse => "The device or usbmuxd gave an unexpected answer. \
+            This usually means there is no pairing record for it: pair it, or import a \
+            pairing file."
+            .to_string(),
+        IdeviceError::Rustls(_) | IdeviceError::PemParseFailed(_) => "The secure session \
+            failed. The pairing record may be out of date: pair the device again, or import \
+            a fresh pairing file."
+            .to_string(),
+        IdeviceError::UsbConnectionRefused => "The device refused the connection. Some \
+            services are unavailable until it has been unlocked since booting."
+            .to_string(),
+        IdeviceError::DeviceNotFound | IdeviceError::UsbBadDevice => {
+            "The device is no longer connected.".to_string()
+        }
+        IdeviceError::UnknownErrorType(t) if t == "MobileGestaltDeprecated" => {
+            "The device no longer answers MobileGestalt queries (iOS 17.4 and later).".to_string()
+        }
+        IdeviceError::ServiceNotFound => {
+            "The diagnostics relay service isn't available on this device.".to_string()
+        }
+        e => format!("Unexpected error: {e}"),
+    }
+}
+
+/// How long to wait before subscribing to hotplug events again
+const HOTPLUG_RETRY: Duration = Duration::from_secs(5);
+
+/// Lists every device connection, or the message explaining why that failed
+///
+/// The list comes first, followed by a message for each device left out of it.
+async fn device_list<B: RegistryBackend>(backend: &B) -> Vec<GuiCommands> {
+    let devs = match backend.list_devices().await {
+        Ok(d) => d,
+        // A socket error means we never reached the muxer at all
+        Err(e @ IdeviceError::Socket(_)) => return vec![GuiCommands::NoUsbmuxd(e)],
+        Err(e) => return vec![GuiCommands::GetDevicesFailure(e)],
+    };
+
+    let mut selections = BTreeMap::new();
+    let mut unreachable = Vec::new();
+    for dev in devs {
+        let values = match backend.device_info(&dev).await {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Failed to get lockdown values: {e:?}");
+                unreachable.push(GuiCommands::DeviceUnreachable(dev.udid, e));
+                continue;
+            }
+        };
+
+        let name = match values.get("DeviceName") {
+            Some(plist::Value::String(n)) => n.clone(),
+            _ => dev.udid.clone(),
+        };
+        selections.insert(DeviceKey::of(&dev), ListedDevice { name, device: dev });
+    }
+    unreachable.insert(0, GuiCommands::Devices(selections));
+    unreachable
+}
+
+/// What a device list looks like to the GUI, for telling whether it changed
+fn fingerprint(devs: &BTreeMap<DeviceKey, ListedDevice>) -> Vec<(DeviceKey, String)> {
+    devs.iter().map(|(k, d)| (k.clone(), d.label())).collect()
+}
+
+/// A finished device listing, and whether the GUI asked for it
+type Listing = (bool, std::thread::Result<Vec<GuiCommands>>);
+
+/// Cancels the device listing in flight and starts another in its place
+///
+/// The new listing answers the GUI if the one it replaces was going to.
+fn replace_listing<B: RegistryBackend>(
+    in_flight: &mut Option<(JoinHandle<()>, bool)>,
+    mut requested: bool,
+    backend: Arc<B>,
+    done: UnboundedSender<Listing>,
+) {
+    if let Some((task, r)) = in_flight.take() {
+        requested |= r && !task.is_finished();
+        task.abort();
+    }
+    let task = tokio::spawn(async move {
+        let msgs = AssertUnwindSafe(device_list(&*backend))
+            .catch_unwind()
+            .await;
+        let _ = done.send((requested, msgs));
+    });
+    *in_flight = Some((task, requested));
+}
+
+/// How long to wait before restarting a crashed idevice task
+const RESTART_DELAY: Duration = Duration::from_millis(500);
+
+/// Runs [`run`], starting it again with a clone of `backend` whenever it panics
+///
+/// Each restart is reported with [`GuiCommands::BackendRestarted`]. Returns once
+/// either side of the GUI's channels is dropped.
+pub async fn supervise<B: RegistryBackend + Clone>(
+    backend: B,
+    mut idevice_receiver: UnboundedReceiver<IdeviceCommands>,
+    gui_sender: UnboundedSender<GuiCommands>,
+) {
+    loop {
+        // Commands go through a channel of our own, so a crash doesn't take the GUI's with it
+        let (sender, receiver) = unbounded_channel();
+        let mut task = tokio::spawn(run(backend.clone(), receiver, gui_sender.clone()));
+        let crash = loop {
+            tokio::select! {
+                command = idevice_receiver.recv() => match command {
+                    Some(c) => {
+                        // A dead task is noticed by the other branch
+                        let _ = sender.send(c);
+                    }
+                    None => {
+                        drop(sender);
+                        let _ = task.await;
+                        return;
+                    }
+                },
+                res = &mut task => match res {
+                    Ok(()) => return,
+                    Err(e) if e.is_panic() => break panic_message(e.into_panic()),
+                    Err(_) => return,
+                },
+            }
+        };
+        error!("The idevice task crashed, restarting it: {crash}");
+        if gui_sender
+            .send(GuiCommands::BackendRestarted(crash))
+            .is_err()
+        {
+            return;
+        }
+        tokio::time::sleep(RESTART_DELAY).await;
+    }
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(s) => *s,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(s) => s.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
+
+/// Cancels the query in flight and starts `query` in its place
+fn replace_query(
+    in_flight: &mut Option<JoinHandle<()>>,
+    id: RequestId,
+    gui_sender: UnboundedSender<GuiCommands>,
+    query: impl Future<Output = Result<Option<plist::Dictionary>, IdeviceError>> + Send + 'static,
+) {
+    if let Some(task) = in_flight.take() {
+        task.abort();
+    }
+    *in_flight = Some(tokio::spawn(async move {
+        let msg = match AssertUnwindSafe(query).catch_unwind().await {
+            Ok(Ok(l)) => GuiCommands::IORegistry(id, l),
+            Ok(Err(e)) => {
+                error!("Query {id} failed: {e:?}");
+                GuiCommands::IORegistryFailure(id, e)
+            }
+            Err(panic) => {
+                let msg = panic_message(panic);
+                error!("Query {id} panicked: {msg}");
+                GuiCommands::IORegistryFailure(id, IdeviceError::InternalError(msg))
+            }
+        };
+        // Nobody to tell if the GUI has gone
+        let _ = gui_sender.send(msg);
+    }));
+}
+
+/// Handles commands from the GUI until the sending side is dropped
+///
+/// The device list is also re-sent whenever the backend reports a device being
+/// attached or detached, once the GUI has asked for it at least once. Requests
+/// to a device run alongside each other, and a panic while answering one is
+/// reported as its failure.
+pub async fn run<B: RegistryBackend>(
+    backend: B,
+    mut idevice_receiver: UnboundedReceiver<IdeviceCommands>,
+    gui_sender: UnboundedSender<GuiCommands>,
+) {
+    let backend = Arc::new(backend);
+    let (hotplug_sender, mut hotplug) = unbounded_channel();
+    let b = backend.clone();
+    let watcher = tokio::spawn(async move {
+        loop {
+            match b.watch_devices(hotplug_sender.clone()).await {
+                Ok(()) => return,
+                Err(e) => debug!("Not listening for devices: {e:?}"),
+            }
+            tokio::time::sleep(HOTPLUG_RETRY).await;
+        }
+    });
+    let mut listed = None;
+    // Lockdown on a device that has gone quiet can take a while to give up, so
+    // listings run on their own and only the latest one is kept
+    let (listing_sender, mut listings) = unbounded_channel::<Listing>();
+    let mut listing_in_flight: Option<(JoinHandle<()>, bool)> = None;
+
+    // Only the latest query runs, so a slow one can't hold up or
+    // overwrite the one typed after it
+    let mut in_flight: Option<JoinHandle<()>> = None;
+    // Likewise for the selected device's info
+    let mut info_in_flight: Option<JoinHandle<()>> = None;
+    'commands: loop {
+        let command = tokio::select! {
+            command = idevice_receiver.recv() => match command {
+                Some(c) => c,
+                None => break,
+            },
+            Some(()) = hotplug.recv() => {
+                // One plug often comes as several events
+                while hotplug.try_recv().is_ok() {}
+                replace_listing(
+                    &mut listing_in_flight,
+                    false,
+                    backend.clone(),
+                    listing_sender.clone(),
+                );
+                continue;
+            }
+            Some((requested, msgs)) = listings.recv() => {
+                // Crash as if the listing had run here, for the supervisor to restart us
+                let msgs = msgs.unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+                if let Some(GuiCommands::Devices(devs)) = msgs.first() {
+                    let fresh = Some(fingerprint(devs));
+                    // Changes are only news once the GUI has asked for the list
+                    if !requested && (listed.is_none() || listed == fresh) {
+                        continue;
+                    }
+                    listed = fresh;
+                } else if !requested {
+                    continue;
+                }
+                for msg in msgs {
+                    if gui_sender.send(msg).is_err() {
+                        break 'commands;
+                    }
+                }
+                continue;
+            }
+        };
+        match command {
+            IdeviceCommands::GetDevices => {
+                replace_listing(
+                    &mut listing_in_flight,
+                    true,
+                    backend.clone(),
+                    listing_sender.clone(),
+                );
+            }
+            IdeviceCommands::IORegistsry(id, (dev, plane, entry, class)) => {
+                let backend = backend.clone();
+                let query = async move { backend.ioregistry(&dev, plane, entry, class).await };
+                replace_query(&mut in_flight, id, gui_sender.clone(), query);
+            }
+            IdeviceCommands::Diagnostics(id, (dev, query)) => {
+                let backend = backend.clone();
+                let query = async move { backend.diagnostics(&dev, query).await };
+                replace_query(&mut in_flight, id, gui_sender.clone(), query);
+            }
+            IdeviceCommands::GetPlanes(dev) => {
+                // Runs alongside queries rather than replacing one
+                let backend = backend.clone();
+                let gui_sender = gui_sender.clone();
+                tokio::spawn(async move {
+                    let planes = match backend.ioregistry(&dev, None, None, None).await {
+                        Ok(root) => root.as_ref().map(registry_planes).unwrap_or_default(),
+                        Err(e) => {
+                            debug!("Failed to get the registry planes: {e:?}");
+                            return;
+                        }
+                    };
+                    if planes.is_empty() {
+                        debug!("The root entry lists no planes");
+                    } else {
+                        let _ = gui_sender.send(GuiCommands::Planes(dev.udid, planes));
+                    }
+                });
+            }
+            IdeviceCommands::Power(dev, action, options) => {
+                // Runs alongside queries, and is answered even if it panics
+                let backend = backend.clone();
+                let gui_sender = gui_sender.clone();
+                tokio::spawn(async move {
+                    let res = AssertUnwindSafe(backend.power(&dev, action, options))
+                        .catch_unwind()
+                        .await
+                        .unwrap_or_else(|panic| {
+                            Err(IdeviceError::InternalError(panic_message(panic)))
+                        });
+                    let msg = match res {
+                        Ok(()) => GuiCommands::PowerSent(dev.udid),
+                        Err(e) => {
+                            error!("Failed to {}: {e:?}", action.request());
+                            GuiCommands::PowerFailure(dev.udid, e)
+                        }
+                    };
+                    let _ = gui_sender.send(msg);
+                });
+            }
+            IdeviceCommands::GetDeviceInfo(dev) => {
+                if let Some(task) = info_in_flight.take() {
+                    task.abort();
+                }
+                let backend = backend.clone();
+                let gui_sender = gui_sender.clone();
+                info_in_flight = Some(tokio::spawn(async move {
+                    let res = AssertUnwindSafe(backend.device_info(&dev))
+                        .catch_unwind()
+                        .await
+                        .unwrap_or_else(|panic| {
+                            Err(IdeviceError::InternalError(panic_message(panic)))
+                        });
+                    let msg = match res {
+                        Ok(v) => GuiCommands::DeviceInfo(device_info_fields(&v)),
+                        Err(e) => {
+                            error!("Failed to get lockdown values: {e:?}");
+                            GuiCommands::DeviceInfoFailure(e)
+                        }
+                    };
+                    let _ = gui_sender.send(msg);
+                }));
+            }
+        };
+    }
+    watcher.abort();
+    eprintln!("Exited idevice loop!!");
+}
@@ -0,0 +1,288 @@
+// Jackson Coxson
+//! Reading `Data` properties as something other than bytes
+//!
+//! Device tree properties such as `compatible`, `model` and `reg` come through
+//! the relay as opaque blobs. The decoding picked for a property key is kept in
+//! a file, so `reg` reads as address and size pairs on every entry from then on.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    model::{EntryId, Registry},
+    paths::data_dir,
+};
+
+/// How many cells `reg` addresses and sizes take when no parent says, as on
+/// Apple's device trees
+const DEFAULT_CELLS: (usize, usize) = (2, 2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Decoding {
+    #[default]
+    Hex,
+    /// NUL separated strings, as in `compatible`
+    Strings,
+    U32,
+    U64,
+    /// Address and size pairs, sized by the parent's `#address-cells` and `#size-cells`
+    Reg,
+}
+
+impl Decoding {
+    pub const ALL: [Self; 5] = [Self::Hex, Self::Strings, Self::U32, Self::U64, Self::Reg];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Hex => "Hex",
+            Self::Strings => "Strings",
+            Self::U32 => "u32 LE",
+            Self::U64 => "u64 LE",
+            Self::Reg => "reg pairs",
+        }
+    }
+
+    /// Name the choice is saved under
+    pub fn key(self) -> &'static str {
+        match self {
+            Self::Hex => "hex",
+            Self::Strings => "strings",
+            Self::U32 => "u32",
+            Self::U64 => "u64",
+            Self::Reg => "reg",
+        }
+    }
+
+    /// A decoding that suits the property, for when none was picked
+    pub fn guess(key: &str, data: &[u8]) -> Self {
+        if key == "reg" {
+            Self::Reg
+        } else if looks_like_strings(data) {
+            Self::Strings
+        } else {
+            Self::Hex
+        }
+    }
+}
+
+impl FromStr for Decoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|d| d.key() == s)
+            .ok_or_else(|| format!("Unknown decoding {s}"))
+    }
+}
+
+/// Whether the data is NUL terminated printable text
+fn looks_like_strings(data: &[u8]) -> bool {
+    data.len() > 1
+        && data.ends_with(&[0])
+        && data[0] != 0
+        && data
+            .iter()
+            .all(|b| *b == 0 || b.is_ascii_graphic() || *b == b' ')
+}
+
+/// Classic hex dump, 16 bytes a line with the printable ones alongside
+pub fn hex_dump(data: &[u8]) -> Vec<String> {
+    data.chunks(16)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut hex = String::new();
+            for j in 0..16 {
+                if j == 8 {
+                    hex.push(' ');
+                }
+                match chunk.get(j) {
+                    Some(b) => hex.push_str(&format!("{b:02x} ")),
+                    None => hex.push_str("   "),
+                }
+            }
+            let ascii: String = chunk
+                .iter()
+                .map(|b| {
+                    if b.is_ascii_graphic() || *b == b' ' {
+                        *b as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            format!("{:08x}  {hex} |{ascii}|", i * 16)
+        })
+        .collect()
+}
+
+/// The strings between NULs, leaving out the empty one after a final NUL
+pub fn strings(data: &[u8]) -> Vec<String> {
+    let data = data.strip_suffix(&[0]).unwrap_or(data);
+    data.split(|b| *b == 0)
+        .map(|s| String::from_utf8_lossy(s).to_string())
+        .collect()
+}
+
+/// Little endian integers of `width` bytes, and how many bytes were left over
+fn integers(data: &[u8], width: usize) -> (Vec<u64>, usize) {
+    let values = data
+        .chunks_exact(width)
+        .map(|c| {
+            let mut bytes = [0; 8];
+            bytes[..width].copy_from_slice(c);
+            u64::from_le_bytes(bytes)
+        })
+        .collect();
+    (values, data.len() % width)
+}
+
+pub fn u32s(data: &[u8]) -> (Vec<u32>, usize) {
+    let (values, rest) = integers(data, 4);
+    (values.into_iter().map(|x| x as u32).collect(), rest)
+}
+
+pub fn u64s(data: &[u8]) -> (Vec<u64>, usize) {
+    integers(data, 8)
+}
+
+/// Address and size pairs of a `reg` property, each `cells` 32 bit cells long
+///
+/// Returns `None` if either is wider than 64 bits.
+pub fn reg_pairs(data: &[u8], cells: (usize, usize)) -> Option<(Vec<(u64, u64)>, usize)> {
+    let (address, size) = cells;
+    if address > 2 || size > 2 || address + size == 0 {
+        return None;
+    }
+    let read = |b: &[u8]| {
+        let mut bytes = [0; 8];
+        bytes[..b.len()].copy_from_slice(b);
+        u64::from_le_bytes(bytes)
+    };
+    let width = (address + size) * 4;
+    let pairs = data
+        .chunks_exact(width)
+        .map(|c| (read(&c[..address * 4]), read(&c[address * 4..])))
+        .collect();
+    Some((pairs, data.len() % width))
+}
+
+/// `#address-cells` and `#size-cells` of the closest entry above `id` that has them
+pub fn reg_cells(reg: &Registry, id: EntryId) -> (usize, usize) {
+    let cells = |id: EntryId, key: &str| match reg[id].properties.get(key) {
+        Some(plist::Value::Data(d)) => u32s(d).0.first().map(|x| *x as usize),
+        Some(plist::Value::Integer(i)) => i.as_unsigned().map(|x| x as usize),
+        _ => None,
+    };
+    reg.ancestors(id)
+        .skip(1)
+        .find_map(|p| Some((cells(p, "#address-cells")?, cells(p, "#size-cells")?)))
+        .unwrap_or(DEFAULT_CELLS)
+}
+
+/// The data as lines of text in the given decoding
+pub fn decode(decoding: Decoding, data: &[u8], cells: (usize, usize)) -> Vec<String> {
+    let leftover = |rest: usize| (rest > 0).then(|| format!("(+{rest} trailing bytes)"));
+    match decoding {
+        Decoding::Hex => hex_dump(data),
+        Decoding::Strings => strings(data)
+            .into_iter()
+            .map(|s| format!("{s:?}"))
+            .collect(),
+        Decoding::U32 => {
+            let (values, rest) = u32s(data);
+            let lines = values
+                .iter()
+                .enumerate()
+                .map(|(i, v)| format!("[{i}] 0x{v:08x} ({v})"));
+            lines.chain(leftover(rest)).collect()
+        }
+        Decoding::U64 => {
+            let (values, rest) = u64s(data);
+            let lines = values
+                .iter()
+                .enumerate()
+                .map(|(i, v)| format!("[{i}] 0x{v:016x} ({v})"));
+            lines.chain(leftover(rest)).collect()
+        }
+        Decoding::Reg => match reg_pairs(data, cells) {
+            Some((pairs, rest)) => {
+                let lines = pairs
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (a, s))| format!("[{i}] address 0x{a:x}, size 0x{s:x}"));
+                lines.chain(leftover(rest)).collect()
+            }
+            None => vec![format!(
+                "Can't read {} address and {} size cells",
+                cells.0, cells.1
+            )],
+        },
+    }
+}
+
+/// Decodings picked per property key, saved to a file whenever one changes
+///
+/// The default store has no file and forgets its choices when dropped.
+#[derive(Debug, Clone, Default)]
+pub struct DecodingStore {
+    path: Option<PathBuf>,
+    choices: BTreeMap<String, Decoding>,
+}
+
+impl DecodingStore {
+    /// `decodings.plist` in the app data directory, if there is one
+    pub fn default_path() -> Option<PathBuf> {
+        Some(data_dir()?.join("decodings.plist"))
+    }
+
+    /// Reads the choices saved at `path`, starting afresh if there are none
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let saved: plist::Dictionary = plist::from_file(&path).unwrap_or_default();
+        let choices = saved
+            .into_iter()
+            .filter_map(|(k, v)| Some((k, v.as_string()?.parse().ok()?)))
+            .collect();
+        Self {
+            path: Some(path),
+            choices,
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// The decoding picked for a property, if any
+    pub fn get(&self, key: &str) -> Option<Decoding> {
+        self.choices.get(key).copied()
+    }
+
+    /// The decoding picked for a property, or a guess from its contents
+    pub fn decoding(&self, key: &str, data: &[u8]) -> Decoding {
+        self.get(key).unwrap_or_else(|| Decoding::guess(key, data))
+    }
+
+    /// Remembers the decoding for every property with this key
+    pub fn set(&mut self, key: &str, decoding: Decoding) -> Result<(), String> {
+        self.choices.insert(key.to_string(), decoding);
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let saved: plist::Dictionary = self
+            .choices
+            .iter()
+            .map(|(k, v)| (k.clone(), plist::Value::from(v.key())))
+            .collect();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {}: {e}", dir.display()))?;
+        }
+        plist::to_file_xml(path, &saved)
+            .map_err(|e| format!("Failed to save decodings to {}: {e}", path.display()))
+    }
+}
@@ -0,0 +1,251 @@
+// Jackson Coxson
+//! Structural diff between two registry snapshots
+//!
+//! Child entries are paired by name and location rather than by index, so an
+//! entry showing up in the middle of a list doesn't make every sibling after it
+//! look changed.
+
+use std::{collections::HashMap, path::Path};
+
+use crate::{
+    export::load_dump,
+    model::{EntryId, Registry},
+    registry::{NodePath, PathSegment, child_path},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// A single difference between two snapshots
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    /// Entry names from the root down, then property keys and array indices
+    pub path: Vec<String>,
+    pub kind: ChangeKind,
+    /// Whether a whole entry was added or removed, rather than a property
+    pub entry: bool,
+    pub old: Option<plist::Value>,
+    pub new: Option<plist::Value>,
+}
+
+impl Change {
+    pub fn path_string(&self) -> String {
+        self.path.join("/")
+    }
+}
+
+/// A registry captured from a device or loaded from a file
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub label: String,
+    pub registry: Registry,
+}
+
+impl Snapshot {
+    /// Loads a plist, JSON or YAML dump
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let registry = load_dump(path)?;
+        Ok(Self {
+            label: path
+                .file_name()
+                .map(|x| x.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            registry,
+        })
+    }
+}
+
+/// Every difference going from `old` to `new`, in tree order
+pub fn diff(old: &Registry, new: &Registry) -> Vec<Change> {
+    let mut out = Vec::new();
+    let sides = Sides { old, new };
+    sides.entry(
+        Registry::ROOT,
+        Registry::ROOT,
+        vec![new.root().label()],
+        &mut out,
+    );
+    out
+}
+
+fn push(out: &mut Vec<Change>, path: &[String], last: String, change: Change) {
+    let mut p = path.to_vec();
+    p.push(last);
+    out.push(Change { path: p, ..change });
+}
+
+/// The two registries being compared
+struct Sides<'a> {
+    old: &'a Registry,
+    new: &'a Registry,
+}
+
+impl Sides<'_> {
+    fn entry(&self, o: EntryId, n: EntryId, path: Vec<String>, out: &mut Vec<Change>) {
+        let (old, new) = (&self.old[o].properties, &self.new[n].properties);
+        for k in old
+            .keys()
+            .chain(new.keys().filter(|k| !old.contains_key(k)))
+        {
+            diff_value(old.get(k), new.get(k), &path, k.clone(), out);
+        }
+
+        // Pair children up by label, in order, so duplicates match first to first
+        let mut unpaired: HashMap<String, Vec<EntryId>> = HashMap::new();
+        for (c, entry) in self.old.children(o).collect::<Vec<_>>().into_iter().rev() {
+            unpaired.entry(entry.label()).or_default().push(c);
+        }
+        for (c, entry) in self.new.children(n) {
+            let label = entry.label();
+            match unpaired.get_mut(&label).and_then(|x| x.pop()) {
+                Some(oc) => {
+                    let mut p = path.clone();
+                    p.push(label);
+                    self.entry(oc, c, p, out);
+                }
+                None => push(
+                    out,
+                    &path,
+                    label,
+                    entry_change(ChangeKind::Added, self.new, c),
+                ),
+            }
+        }
+        for (c, entry) in self.old.children(o) {
+            let label = entry.label();
+            if let Some(left) = unpaired.get_mut(&label)
+                && let Some(i) = left.iter().position(|x| *x == c)
+            {
+                left.remove(i);
+                push(
+                    out,
+                    &path,
+                    label,
+                    entry_change(ChangeKind::Removed, self.old, c),
+                );
+            }
+        }
+    }
+}
+
+fn entry_change(kind: ChangeKind, reg: &Registry, id: EntryId) -> Change {
+    let value = Some(plist::Value::Dictionary(reg.to_dictionary(id)));
+    Change {
+        path: Vec::new(),
+        kind,
+        entry: true,
+        old: if kind == ChangeKind::Removed {
+            value.clone()
+        } else {
+            None
+        },
+        new: if kind == ChangeKind::Added {
+            value
+        } else {
+            None
+        },
+    }
+}
+
+fn diff_value(
+    old: Option<&plist::Value>,
+    new: Option<&plist::Value>,
+    path: &[String],
+    key: String,
+    out: &mut Vec<Change>,
+) {
+    let kind = match (old, new) {
+        (None, None) => return,
+        (None, Some(_)) => ChangeKind::Added,
+        (Some(_), None) => ChangeKind::Removed,
+        (Some(plist::Value::Dictionary(o)), Some(plist::Value::Dictionary(n))) => {
+            let mut p = path.to_vec();
+            p.push(key);
+            for k in o.keys().chain(n.keys().filter(|k| !o.contains_key(k))) {
+                diff_value(o.get(k), n.get(k), &p, k.clone(), out);
+            }
+            return;
+        }
+        (Some(plist::Value::Array(o)), Some(plist::Value::Array(n))) => {
+            let mut p = path.to_vec();
+            p.push(key);
+            for i in 0..o.len().max(n.len()) {
+                diff_value(o.get(i), n.get(i), &p, format!("[{i}]"), out);
+            }
+            return;
+        }
+        (Some(o), Some(n)) if o == n => return,
+        (Some(_), Some(_)) => ChangeKind::Changed,
+    };
+    push(
+        out,
+        path,
+        key,
+        Change {
+            path: Vec::new(),
+            kind,
+            entry: false,
+            old: old.cloned(),
+            new: new.cloned(),
+        },
+    );
+}
+
+/// Paths in `new` whose value differs from the same path in `old`
+///
+/// Unlike [`diff`] this goes by position, which is what highlighting rows of a
+/// re-polled query in place needs.
+pub fn changed_paths(old: &Registry, new: &Registry) -> Vec<NodePath> {
+    let mut out = Vec::new();
+    changed_entry(old, Some(Registry::ROOT), new, Registry::ROOT, &mut out);
+    out
+}
+
+/// Compares an entry of `new` with whatever entry of `old` was at the same place
+fn changed_entry(
+    old: &Registry,
+    o: Option<EntryId>,
+    new: &Registry,
+    n: EntryId,
+    out: &mut Vec<NodePath>,
+) {
+    let entry = &new[n];
+    let Some(o) = o else {
+        out.push(entry.node_path.clone());
+        return;
+    };
+    for (k, v) in &entry.properties {
+        let p = child_path(&entry.node_path, PathSegment::Key(k.clone()));
+        changed_value(old[o].properties.get(k), v, p, out);
+    }
+    for (c, child) in new.children(n) {
+        changed_entry(old, old.entry_at(&child.node_path), new, c, out);
+    }
+}
+
+fn changed_value(
+    old: Option<&plist::Value>,
+    new: &plist::Value,
+    path: NodePath,
+    out: &mut Vec<NodePath>,
+) {
+    match (old, new) {
+        (Some(plist::Value::Dictionary(o)), plist::Value::Dictionary(n)) => {
+            for (k, v) in n {
+                let p = child_path(&path, PathSegment::Key(k.clone()));
+                changed_value(o.get(k), v, p, out);
+            }
+        }
+        (Some(plist::Value::Array(o)), plist::Value::Array(n)) => {
+            for (i, v) in n.iter().enumerate() {
+                changed_value(o.get(i), v, child_path(&path, PathSegment::Index(i)), out);
+            }
+        }
+        (Some(o), n) if o == n => {}
+        _ => out.push(path),
+    }
+}
@@ -0,0 +1,168 @@
+// Jackson Coxson
+//! Window comparing two registry snapshots
+
+use egui::{Color32, RichText};
+use rfd::FileDialog;
+
+use crate::{
+    diff::{Change, ChangeKind, Snapshot, diff},
+    registry::{entry_class, format_value},
+};
+
+/// Something the window needs the app to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffAction {
+    /// Query the selected device and store the result in this slot
+    Capture(usize),
+}
+
+#[derive(Default)]
+pub struct DiffView {
+    pub open: bool,
+    snapshots: [Option<Snapshot>; 2],
+    changes: Option<Vec<Change>>,
+    error: Option<String>,
+    /// Slot waiting on a live capture
+    pending: Option<usize>,
+}
+
+impl DiffView {
+    pub fn snapshot(&self, slot: usize) -> Option<&Snapshot> {
+        self.snapshots[slot].as_ref()
+    }
+
+    /// Stores a snapshot, re-diffing once both slots are filled
+    pub fn set_snapshot(&mut self, slot: usize, snapshot: Snapshot) {
+        self.snapshots[slot] = Some(snapshot);
+        self.error = None;
+        self.changes = match &self.snapshots {
+            [Some(a), Some(b)] => Some(diff(&a.registry, &b.registry)),
+            _ => None,
+        };
+    }
+
+    pub fn load_file(&mut self, slot: usize, path: &std::path::Path) -> Result<(), String> {
+        let snapshot = Snapshot::from_file(path)?;
+        self.set_snapshot(slot, snapshot);
+        Ok(())
+    }
+
+    /// The slot the next live registry should go into, if a capture is waiting
+    pub fn take_pending(&mut self) -> Option<usize> {
+        self.pending.take()
+    }
+
+    pub fn set_pending(&mut self, slot: usize) {
+        self.pending = Some(slot);
+    }
+
+    /// Gives up on a waiting capture, showing why
+    pub fn capture_failed(&mut self, error: String) {
+        if self.pending.take().is_some() {
+            self.error = Some(error);
+        }
+    }
+
+    /// Why the last capture or load failed
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub fn changes(&self) -> Option<&[Change]> {
+        self.changes.as_deref()
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, can_capture: bool) -> Option<DiffAction> {
+        let mut action = None;
+        let mut open = self.open;
+        egui::Window::new("Diff")
+            .open(&mut open)
+            .default_size([900.0, 500.0])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    for (slot, name) in ["Before", "After"].into_iter().enumerate() {
+                        ui.vertical(|ui| {
+                            ui.heading(name);
+                            match (&self.snapshots[slot], self.pending == Some(slot)) {
+                                (_, true) => ui.label("Capturing..."),
+                                (Some(s), false) => ui.label(&s.label),
+                                (None, false) => ui.label("Empty"),
+                            };
+                            ui.horizontal(|ui| {
+                                if ui
+                                    .add_enabled(can_capture, egui::Button::new("Capture"))
+                                    .on_disabled_hover_text("Select a device first")
+                                    .clicked()
+                                {
+                                    action = Some(DiffAction::Capture(slot));
+                                }
+                                if ui.button("Open File...").clicked()
+                                    && let Some(p) = FileDialog::new()
+                                        .add_filter(
+                                            "Registry dump",
+                                            &["plist", "json", "yaml", "yml"],
+                                        )
+                                        .pick_file()
+                                    && let Err(e) = self.load_file(slot, &p)
+                                {
+                                    self.error = Some(e);
+                                }
+                            });
+                        });
+                        ui.separator();
+                    }
+                });
+                if let Some(e) = &self.error {
+                    ui.label(RichText::new(e).color(Color32::RED));
+                }
+                ui.separator();
+
+                let Some(changes) = &self.changes else {
+                    ui.label("Capture or open two snapshots to compare them.");
+                    return;
+                };
+                if changes.is_empty() {
+                    ui.label("No differences.");
+                    return;
+                }
+                ui.label(format!("{} differences", changes.len()));
+                egui::ScrollArea::both()
+                    .auto_shrink([false, false])
+                    .show(ui, |ui| {
+                        egui::Grid::new("diff_grid")
+                            .striped(true)
+                            .num_columns(4)
+                            .show(ui, |ui| {
+                                ui.strong("");
+                                ui.strong("Path");
+                                ui.strong("Before");
+                                ui.strong("After");
+                                ui.end_row();
+                                for c in changes {
+                                    change_row(ui, c);
+                                }
+                            });
+                    });
+            });
+        self.open = open;
+        action
+    }
+}
+
+fn change_row(ui: &mut egui::Ui, change: &Change) {
+    let (symbol, color) = match change.kind {
+        ChangeKind::Added => ("+", Color32::from_rgb(0, 170, 0)),
+        ChangeKind::Removed => ("-", Color32::from_rgb(220, 50, 50)),
+        ChangeKind::Changed => ("~", Color32::from_rgb(230, 150, 0)),
+    };
+    let value = |v: &Option<plist::Value>| match v {
+        Some(plist::Value::Dictionary(d)) if change.entry => format!("<class {}>", entry_class(d)),
+        Some(v) => format_value(v),
+        None => String::new(),
+    };
+    ui.label(RichText::new(symbol).monospace().strong().color(color));
+    ui.label(RichText::new(change.path_string()).monospace().color(color));
+    ui.label(RichText::new(value(&change.old)).monospace());
+    ui.label(RichText::new(value(&change.new)).monospace());
+    ui.end_row();
+}
@@ -0,0 +1,205 @@
+// Jackson Coxson
+//! Reading and writing registry dumps as plist, JSON or YAML
+
+use std::{path::Path, str::FromStr};
+
+use base64::Engine;
+
+use crate::model::Registry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    #[default]
+    XmlPlist,
+    BinaryPlist,
+    Json,
+    Yaml,
+}
+
+impl ExportFormat {
+    pub const ALL: [Self; 4] = [Self::XmlPlist, Self::BinaryPlist, Self::Json, Self::Yaml];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::XmlPlist => "XML plist",
+            Self::BinaryPlist => "Binary plist",
+            Self::Json => "JSON",
+            Self::Yaml => "YAML",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::XmlPlist | Self::BinaryPlist => "plist",
+            Self::Json => "json",
+            Self::Yaml => "yaml",
+        }
+    }
+
+    /// Whether `Data` values need a text encoding in this format
+    pub fn encodes_data(self) -> bool {
+        matches!(self, Self::Json | Self::Yaml)
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "xml" | "plist" => Ok(Self::XmlPlist),
+            "binary" | "bplist" => Ok(Self::BinaryPlist),
+            "json" => Ok(Self::Json),
+            "yaml" | "yml" => Ok(Self::Yaml),
+            _ => Err(format!(
+                "Unknown format {s}, expected xml, binary, json or yaml"
+            )),
+        }
+    }
+}
+
+/// How `Data` values are written in formats without a binary type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DataEncoding {
+    #[default]
+    Base64,
+    Hex,
+}
+
+impl DataEncoding {
+    pub const ALL: [Self; 2] = [Self::Base64, Self::Hex];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Base64 => "Base64",
+            Self::Hex => "Hex",
+        }
+    }
+
+    pub fn encode(self, data: &[u8]) -> String {
+        match self {
+            Self::Base64 => base64::engine::general_purpose::STANDARD.encode(data),
+            Self::Hex => data.iter().map(|b| format!("{b:02x}")).collect(),
+        }
+    }
+}
+
+impl FromStr for DataEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "base64" => Ok(Self::Base64),
+            "hex" => Ok(Self::Hex),
+            _ => Err(format!("Unknown encoding {s}, expected base64 or hex")),
+        }
+    }
+}
+
+/// Serializes a registry in the given format
+pub fn export(
+    reg: &Registry,
+    format: ExportFormat,
+    data_encoding: DataEncoding,
+) -> Result<Vec<u8>, String> {
+    let reg = reg.to_dictionary(Registry::ROOT);
+    let mut out = Vec::new();
+    match format {
+        ExportFormat::XmlPlist => {
+            plist::to_writer_xml(&mut out, &reg).map_err(|e| e.to_string())?;
+        }
+        ExportFormat::BinaryPlist => {
+            plist::to_writer_binary(&mut out, &reg).map_err(|e| e.to_string())?;
+        }
+        ExportFormat::Json => {
+            let value = to_json(&plist::Value::Dictionary(reg), data_encoding);
+            serde_json::to_writer_pretty(&mut out, &value).map_err(|e| e.to_string())?;
+            out.push(b'\n');
+        }
+        ExportFormat::Yaml => {
+            let value = to_json(&plist::Value::Dictionary(reg), data_encoding);
+            serde_yaml_ng::to_writer(&mut out, &value).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(out)
+}
+
+/// Converts a plist value to JSON without losing information
+///
+/// Integers keep their full signed or unsigned range, dates become ISO 8601
+/// strings, and reals JSON can't hold (NaN, infinities) become strings.
+pub fn to_json(value: &plist::Value, data_encoding: DataEncoding) -> serde_json::Value {
+    use serde_json::Value as J;
+    match value {
+        plist::Value::Array(a) => J::Array(a.iter().map(|v| to_json(v, data_encoding)).collect()),
+        plist::Value::Dictionary(d) => J::Object(
+            d.iter()
+                .map(|(k, v)| (k.clone(), to_json(v, data_encoding)))
+                .collect(),
+        ),
+        plist::Value::Boolean(b) => J::Bool(*b),
+        plist::Value::Data(d) => J::String(data_encoding.encode(d)),
+        plist::Value::Date(d) => J::String(d.to_xml_format()),
+        plist::Value::Real(r) => match serde_json::Number::from_f64(*r) {
+            Some(n) => J::Number(n),
+            None => J::String(r.to_string()),
+        },
+        plist::Value::Integer(i) => match (i.as_signed(), i.as_unsigned()) {
+            (Some(s), _) => J::from(s),
+            (None, Some(u)) => J::from(u),
+            (None, None) => J::Null,
+        },
+        plist::Value::String(s) => J::String(s.clone()),
+        plist::Value::Uid(u) => J::from(u.get()),
+        _ => J::Null,
+    }
+}
+
+/// Loads a dump written by [`export`], or by `ioreg`/Xcode as a plist
+///
+/// XML and binary plists come back exactly. JSON and YAML have no Data or Date
+/// types, so those values come back as the strings they were written as.
+pub fn load_dump(path: &Path) -> Result<Registry, String> {
+    let bytes =
+        std::fs::read(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+    import(&bytes)
+        .map(Registry::from)
+        .map_err(|e| format!("Failed to load {}: {e}", path.display()))
+}
+
+/// Parses a dump, detecting its format from the contents
+pub fn import(bytes: &[u8]) -> Result<plist::Dictionary, String> {
+    let text = std::str::from_utf8(bytes).unwrap_or_default().trim_start();
+    if bytes.starts_with(b"bplist") || text.starts_with("<?xml") || text.starts_with("<plist") {
+        return plist::from_bytes(bytes).map_err(|e| e.to_string());
+    }
+
+    let value: serde_json::Value = if text.starts_with('{') {
+        serde_json::from_str(text).map_err(|e| e.to_string())?
+    } else {
+        serde_yaml_ng::from_str(text).map_err(|e| e.to_string())?
+    };
+    match from_json(value) {
+        plist::Value::Dictionary(d) => Ok(d),
+        _ => Err("The dump isn't a dictionary".to_string()),
+    }
+}
+
+fn from_json(value: serde_json::Value) -> plist::Value {
+    use serde_json::Value as J;
+    match value {
+        J::Null => plist::Value::String(String::new()),
+        J::Bool(b) => b.into(),
+        J::Number(n) => match (n.as_i64(), n.as_u64(), n.as_f64()) {
+            (Some(i), _, _) => i.into(),
+            (None, Some(u), _) => u.into(),
+            (None, None, Some(f)) => f.into(),
+            _ => plist::Value::String(n.to_string()),
+        },
+        J::String(s) => s.into(),
+        J::Array(a) => plist::Value::Array(a.into_iter().map(from_json).collect()),
+        J::Object(o) => {
+            plist::Value::Dictionary(o.into_iter().map(|(k, v)| (k, from_json(v))).collect())
+        }
+    }
+}
@@ -0,0 +1,76 @@
+// Jackson Coxson
+//! Names and classes of every entry in a registry, for completing queries
+//! without asking the device
+
+use std::collections::BTreeMap;
+
+use crate::model::Registry;
+
+/// Keys an entry's class can be under. Either can be the one a query matches.
+const CLASS_KEYS: [&str; 2] = ["IOClass", "IOObjectClass"];
+
+/// Every entry of a registry by name and class
+#[derive(Debug, Clone, Default)]
+pub struct RegistryIndex {
+    /// Name and classes of each entry, in tree order
+    entries: Vec<(String, Vec<String>)>,
+    /// How many entries have each name
+    names: BTreeMap<String, usize>,
+    /// How many entries have each class
+    classes: BTreeMap<String, usize>,
+}
+
+impl RegistryIndex {
+    pub fn build(reg: &Registry) -> Self {
+        let mut index = Self::default();
+        // Entries without a name can't be queried by one
+        for (_, entry) in reg.entries() {
+            let props = &entry.properties;
+            if !props.contains_key("IORegistryEntryName") {
+                continue;
+            }
+            *index.names.entry(entry.name.clone()).or_default() += 1;
+            let mut classes: Vec<String> = CLASS_KEYS
+                .iter()
+                .filter_map(|k| props.get(k).and_then(|x| x.as_string()))
+                .map(ToString::to_string)
+                .collect();
+            classes.dedup();
+            for c in &classes {
+                *index.classes.entry(c.clone()).or_default() += 1;
+            }
+            index.entries.push((entry.name.clone(), classes));
+        }
+        index
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Entry names, sorted
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.keys().map(String::as_str)
+    }
+
+    /// Classes, sorted
+    pub fn classes(&self) -> impl Iterator<Item = &str> {
+        self.classes.keys().map(String::as_str)
+    }
+
+    /// How many entries a query for this name and class would find
+    ///
+    /// Names and classes must match exactly, as the relay requires.
+    pub fn count(&self, name: Option<&str>, class: Option<&str>) -> usize {
+        match (name, class) {
+            (None, None) => self.entries.len(),
+            (Some(n), None) => self.names.get(n).copied().unwrap_or_default(),
+            (None, Some(c)) => self.classes.get(c).copied().unwrap_or_default(),
+            (Some(n), Some(c)) => self
+                .entries
+                .iter()
+                .filter(|(name, classes)| name == n && classes.iter().any(|x| x == c))
+                .count(),
+        }
+    }
+}
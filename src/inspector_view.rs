@@ -0,0 +1,120 @@
+// Jackson Coxson
+//! Window showing a `Data` property as hex and ASCII, and decoded
+
+use egui::{Color32, RichText};
+
+use crate::{
+    decode::{Decoding, DecodingStore, decode, hex_dump, reg_cells},
+    model::Registry,
+    registry::{NodePath, PathSegment},
+};
+
+pub struct DataInspector {
+    pub open: bool,
+    store: DecodingStore,
+    path: Option<NodePath>,
+    save_error: Option<String>,
+}
+
+impl DataInspector {
+    pub fn new(store: DecodingStore) -> Self {
+        Self {
+            open: false,
+            store,
+            path: None,
+            save_error: None,
+        }
+    }
+
+    /// Shows the property at `path`
+    pub fn inspect(&mut self, path: &NodePath) {
+        self.path = Some(path.clone());
+        self.open = true;
+    }
+
+    pub fn path(&self) -> Option<&NodePath> {
+        self.path.as_ref()
+    }
+
+    pub fn store(&self) -> &DecodingStore {
+        &self.store
+    }
+
+    /// Picks how every property with this key is decoded
+    pub fn set_decoding(&mut self, key: &str, decoding: Decoding) -> Result<(), String> {
+        let res = self.store.set(key, decoding);
+        self.save_error = res.clone().err();
+        res
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, reg: Option<&Registry>) {
+        let mut open = self.open;
+        let mut picked = None;
+        egui::Window::new("Data")
+            .open(&mut open)
+            .default_size([560.0, 360.0])
+            .show(ctx, |ui| {
+                let inspected = reg.zip(self.path.as_ref()).and_then(|(reg, path)| {
+                    let Some(plist::Value::Data(data)) = reg.lookup(path) else {
+                        return None;
+                    };
+                    let cells = reg_cells(reg, reg.entry_at(path)?);
+                    Some((reg, path, data, cells))
+                });
+                let Some((reg, path, data, cells)) = inspected else {
+                    ui.label("The property is gone from the registry being shown.");
+                    return;
+                };
+                // Items of an array go by the array's key
+                let key = path
+                    .iter()
+                    .rev()
+                    .find_map(|s| match s {
+                        PathSegment::Key(k) => Some(k.as_str()),
+                        PathSegment::Index(_) => None,
+                    })
+                    .unwrap_or_default();
+
+                ui.label(
+                    RichText::new(format!("{} ({} bytes)", reg.path_label(path), data.len()))
+                        .monospace(),
+                );
+                let current = self.store.decoding(key, data);
+                ui.horizontal(|ui| {
+                    for d in Decoding::ALL {
+                        if ui.selectable_label(current == d, d.name()).clicked() {
+                            picked = Some((key.to_string(), d));
+                        }
+                    }
+                });
+                match self.store.get(key) {
+                    Some(_) => ui.label(format!("Used for every {key} property")),
+                    None => ui.label(RichText::new("Guessed from the contents").italics()),
+                };
+                if let Some(e) = &self.save_error {
+                    ui.label(RichText::new(e).color(Color32::RED));
+                }
+                ui.separator();
+
+                let lines = decode(current, data, cells);
+                egui::ScrollArea::both()
+                    .auto_shrink([false, false])
+                    .show(ui, |ui| {
+                        for line in &lines {
+                            ui.label(RichText::new(line).monospace());
+                        }
+                        if current != Decoding::Hex {
+                            ui.separator();
+                            for line in hex_dump(data) {
+                                ui.label(RichText::new(line).monospace().color(Color32::GRAY));
+                            }
+                        }
+                    });
+            });
+        self.open = open;
+        if let Some((key, d)) = picked {
+            // Shown in the window, nothing more to do with it here
+            let _ = self.set_decoding(&key, d);
+        }
+    }
+}
@@ -0,0 +1,31 @@
+// Jackson Coxson
+//! Querying the IORegistry of iOS devices
+//!
+//! The querying logic lives behind [`backend::RegistryBackend`] so it can be used
+//! without the GUI. Disable the default `gui` feature to drop eframe and friends.
+
+#[cfg(feature = "gui")]
+pub mod app;
+pub mod backend;
+pub mod commands;
+pub mod decode;
+pub mod diff;
+#[cfg(feature = "gui")]
+pub mod diff_view;
+pub mod export;
+pub mod index;
+#[cfg(feature = "gui")]
+pub mod inspector_view;
+pub mod model;
+pub mod pairing;
+#[cfg(feature = "gui")]
+pub mod pairing_view;
+pub mod paths;
+#[cfg(feature = "gui")]
+pub mod plot_view;
+pub mod power;
+pub mod registry;
+pub mod search;
+pub mod series;
+#[cfg(feature = "gui")]
+pub mod tree_view;
@@ -0,0 +1,288 @@
+// Jackson Coxson
+//! Typed tree of registry entries, parsed once from what the relay returns
+//!
+//! Entries are kept in one list in tree order and refer to each other by
+//! [`EntryId`], so going up to a parent is as cheap as going down to a child.
+//! Each entry remembers where it was in the dictionary it came from, which is
+//! how the tree, search results and pinned series address it.
+
+use std::ops::Index;
+
+use crate::registry::{
+    CHILDREN_KEY, NodePath, PLANES_KEY, PathSegment, child_path, entry_class, entry_name,
+};
+
+/// Stands for the entries above a queried entry in its paths
+pub const ELIDED: &str = "…";
+
+/// Position of an entry in its [`Registry`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EntryId(usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegistryEntry {
+    pub name: String,
+    pub class: String,
+    /// Unit address, e.g. `10F00000` for `arm-io@10F00000`
+    pub location: Option<String>,
+    /// Every key but the children, the name and class included
+    pub properties: plist::Dictionary,
+    pub children: Vec<EntryId>,
+    pub parent: Option<EntryId>,
+    /// Where the entry is in the dictionary it was parsed from
+    pub node_path: NodePath,
+}
+
+impl RegistryEntry {
+    /// Identifies the entry among its siblings, e.g. `arm-io@10F00000`
+    pub fn label(&self) -> String {
+        match &self.location {
+            Some(loc) => format!("{}@{loc}", self.name),
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// A registry, or one entry of it, as fetched from a device or loaded from a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Registry {
+    /// The plane the registry was queried in, if known
+    plane: Option<String>,
+    /// Every entry in tree order, the root first
+    entries: Vec<RegistryEntry>,
+}
+
+impl Registry {
+    pub const ROOT: EntryId = EntryId(0);
+
+    pub fn new(reg: plist::Dictionary, plane: Option<&str>) -> Self {
+        let mut registry = Self {
+            plane: plane.map(ToString::to_string),
+            entries: Vec::new(),
+        };
+        registry.add(reg, None, Vec::new());
+        registry
+    }
+
+    fn add(&mut self, entry: plist::Dictionary, parent: Option<EntryId>, path: NodePath) {
+        let id = EntryId(self.entries.len());
+        let mut properties = plist::Dictionary::new();
+        let mut children = Vec::new();
+        for (k, v) in entry {
+            match v {
+                plist::Value::Array(a) if k == CHILDREN_KEY => children = a,
+                v => {
+                    properties.insert(k, v);
+                }
+            }
+        }
+        self.entries.push(RegistryEntry {
+            name: entry_name(&properties).to_string(),
+            class: entry_class(&properties).to_string(),
+            location: properties
+                .get("IORegistryEntryLocation")
+                .and_then(|x| x.as_string())
+                .map(ToString::to_string),
+            properties,
+            children: Vec::new(),
+            parent,
+            node_path: path.clone(),
+        });
+
+        let children_path = child_path(&path, PathSegment::Key(CHILDREN_KEY.to_string()));
+        for (i, c) in children.into_iter().enumerate() {
+            // Anything that isn't a dictionary isn't an entry
+            if let plist::Value::Dictionary(c) = c {
+                let child = EntryId(self.entries.len());
+                self.entries[id.0].children.push(child);
+                self.add(
+                    c,
+                    Some(id),
+                    child_path(&children_path, PathSegment::Index(i)),
+                );
+            }
+        }
+    }
+
+    pub fn plane(&self) -> Option<&str> {
+        self.plane.as_deref()
+    }
+
+    pub fn root(&self) -> &RegistryEntry {
+        &self[Self::ROOT]
+    }
+
+    /// Every entry in tree order
+    pub fn entries(&self) -> impl Iterator<Item = (EntryId, &RegistryEntry)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (EntryId(i), e))
+    }
+
+    pub fn children(&self, id: EntryId) -> impl Iterator<Item = (EntryId, &RegistryEntry)> {
+        self[id].children.iter().map(|c| (*c, &self[*c]))
+    }
+
+    /// The entry and each entry above it, ending at the root
+    pub fn ancestors(&self, id: EntryId) -> impl Iterator<Item = EntryId> {
+        std::iter::successors(Some(id), |x| self[*x].parent)
+    }
+
+    /// Whether the root is a whole plane's, rather than an entry a query asked for
+    pub fn is_plane_root(&self) -> bool {
+        self.root().properties.contains_key(PLANES_KEY)
+    }
+
+    /// Where an entry is, e.g. `IOService:/AppleARMPE/arm-io@10F00000`
+    ///
+    /// The plane's root is left out, and so is the plane if it isn't known.
+    /// Below an entry that was queried on its own, what is above it isn't known,
+    /// so the path starts with [`ELIDED`], e.g. `IOService:/…/AppleARMPMUCharger`.
+    pub fn path(&self, id: EntryId) -> String {
+        let whole_plane = self.is_plane_root();
+        let mut labels: Vec<String> = self
+            .ancestors(id)
+            .filter(|x| !whole_plane || *x != Self::ROOT)
+            .map(|x| self[x].label())
+            .collect();
+        if !whole_plane {
+            labels.push(ELIDED.to_string());
+        }
+        labels.reverse();
+        match self.plane() {
+            Some(plane) => format!("{plane}:/{}", labels.join("/")),
+            None => format!("/{}", labels.join("/")),
+        }
+    }
+
+    /// The entry at a path as [`Registry::path`] writes it
+    ///
+    /// The plane can be left out, but if given must be the one queried. A step
+    /// without a location matches the first entry of that name, wherever it is.
+    /// If only an entry was queried, the path is followed from where it names
+    /// that entry, so full paths work as well as elided ones.
+    pub fn find(&self, path: &str) -> Option<EntryId> {
+        let path = path.trim();
+        let steps = match path.split_once(":/") {
+            Some((plane, rest)) => {
+                if self.plane().is_some_and(|p| p != plane) {
+                    return None;
+                }
+                rest
+            }
+            None => path,
+        };
+
+        let mut steps = steps.split('/').filter(|s| !s.is_empty());
+        if !self.is_plane_root() {
+            steps.find(|s| is_step(self.root(), s))?;
+        }
+        let mut id = Self::ROOT;
+        for step in steps {
+            id = self.children(id).find(|(_, c)| is_step(c, step))?.0;
+        }
+        Some(id)
+    }
+
+    /// The child that was at index `i` of an entry's children array
+    fn child_at(&self, id: EntryId, i: usize) -> Option<EntryId> {
+        self[id]
+            .children
+            .iter()
+            .copied()
+            .find(|c| self[*c].node_path.last() == Some(&PathSegment::Index(i)))
+    }
+
+    /// Follows the entries at the start of `path`, returning the last one and
+    /// what is left of the path inside it
+    fn walk<'a>(&self, path: &'a [PathSegment]) -> Option<(EntryId, &'a [PathSegment])> {
+        let mut id = Self::ROOT;
+        let mut rest = path;
+        while let [PathSegment::Key(k), PathSegment::Index(i), tail @ ..] = rest
+            && k == CHILDREN_KEY
+        {
+            id = self.child_at(id, *i)?;
+            rest = tail;
+        }
+        Some((id, rest))
+    }
+
+    /// The entry a node is in, or is
+    pub fn entry_at(&self, path: &NodePath) -> Option<EntryId> {
+        self.walk(path).map(|(id, _)| id)
+    }
+
+    /// The property value at `path`, if the registry has one there
+    pub fn lookup(&self, path: &NodePath) -> Option<&plist::Value> {
+        let (id, rest) = self.walk(path)?;
+        let (PathSegment::Key(k), rest) = rest.split_first()? else {
+            return None;
+        };
+        let mut value = self[id].properties.get(k)?;
+        for segment in rest {
+            value = match (segment, value) {
+                (PathSegment::Key(k), plist::Value::Dictionary(d)) => d.get(k)?,
+                (PathSegment::Index(i), plist::Value::Array(a)) => a.get(*i)?,
+                _ => return None,
+            };
+        }
+        Some(value)
+    }
+
+    /// Readable name for a node, e.g. `AppleARMPMUCharger/InstantAmperage`
+    ///
+    /// Entries are named rather than indexed, and the root is left out.
+    pub fn path_label(&self, path: &NodePath) -> String {
+        let Some((id, rest)) = self.walk(path) else {
+            return String::new();
+        };
+        let mut parts: Vec<String> = self
+            .ancestors(id)
+            .filter(|x| *x != Self::ROOT)
+            .map(|x| self[x].name.clone())
+            .collect();
+        parts.reverse();
+        parts.extend(rest.iter().map(|s| match s {
+            PathSegment::Key(k) => k.clone(),
+            PathSegment::Index(i) => format!("[{i}]"),
+        }));
+        parts.join("/")
+    }
+
+    /// An entry and everything below it as the relay would send it
+    pub fn to_dictionary(&self, id: EntryId) -> plist::Dictionary {
+        let mut dict = self[id].properties.clone();
+        if !self[id].children.is_empty() {
+            let children = self
+                .children(id)
+                .map(|(c, _)| plist::Value::Dictionary(self.to_dictionary(c)))
+                .collect();
+            dict.insert(CHILDREN_KEY.to_string(), plist::Value::Array(children));
+        }
+        dict
+    }
+}
+
+/// Whether a step of a path names this entry, with or without its location
+fn is_step(entry: &RegistryEntry, step: &str) -> bool {
+    if step.contains('@') {
+        entry.label() == step
+    } else {
+        entry.name == step
+    }
+}
+
+impl Index<EntryId> for Registry {
+    type Output = RegistryEntry;
+
+    fn index(&self, id: EntryId) -> &RegistryEntry {
+        &self.entries[id.0]
+    }
+}
+
+impl From<plist::Dictionary> for Registry {
+    fn from(reg: plist::Dictionary) -> Self {
+        Self::new(reg, None)
+    }
+}
@@ -0,0 +1,149 @@
+// Jackson Coxson
+//! Pairing records imported by the user, kept as one file per UDID
+//!
+//! These let us talk to a device without the host's usbmuxd pairing store,
+//! which is the only way to reach one directly over Wi-Fi.
+
+use std::path::{Path, PathBuf};
+
+use idevice::pairing_file::PairingFile;
+
+use crate::paths::data_dir;
+
+/// Overrides where pairing records are kept
+pub const PAIRING_DIR_ENV: &str = "IOREG_EXPLORER_PAIRING_DIR";
+
+/// A record in the store
+#[derive(Debug, Clone)]
+pub struct StoredPairing {
+    pub udid: String,
+    pub wifi_mac_address: String,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct PairingStore {
+    dir: PathBuf,
+}
+
+impl PairingStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// `IOREG_EXPLORER_PAIRING_DIR`, or a folder in the platform's app data directory
+    pub fn default_dir() -> Option<PathBuf> {
+        if let Some(dir) = std::env::var_os(PAIRING_DIR_ENV) {
+            return Some(dir.into());
+        }
+        Some(data_dir()?.join("pairing"))
+    }
+
+    /// The store in [`Self::default_dir`]
+    pub fn open_default() -> Result<Self, String> {
+        Self::default_dir().map(Self::new).ok_or_else(|| {
+            format!(
+                "There is no app data directory to keep pairing files in, set {PAIRING_DIR_ENV}"
+            )
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Copies a pairing file into the store, returning the UDID it was filed under
+    ///
+    /// The UDID comes from `udid`, then the record itself, then the file name, as
+    /// usbmuxd names its records `<UDID>.plist` without always storing it inside.
+    pub fn import(&self, path: &Path, udid: Option<&str>) -> Result<String, String> {
+        let bytes =
+            std::fs::read(path).map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+        let record = PairingFile::from_bytes(&bytes)
+            .map_err(|e| format!("{} isn't a valid pairing file: {e}", path.display()))?;
+        let udid = udid
+            .map(ToString::to_string)
+            .or(record.udid)
+            .or_else(|| {
+                path.file_stem()
+                    .map(|x| x.to_string_lossy().to_string())
+                    .filter(|x| looks_like_udid(x))
+            })
+            .ok_or("The pairing file doesn't say which device it's for, give a UDID")?;
+        let dest = self.path_for(&udid)?;
+
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("Failed to create {}: {e}", self.dir.display()))?;
+        std::fs::write(&dest, bytes)
+            .map_err(|e| format!("Failed to write {}: {e}", dest.display()))?;
+        Ok(udid)
+    }
+
+    /// Removes a device's record
+    pub fn delete(&self, udid: &str) -> Result<(), String> {
+        let path = self.path_for(udid)?;
+        if !path.exists() {
+            return Err(format!("No pairing file for {udid}"));
+        }
+        std::fs::remove_file(&path).map_err(|e| format!("Failed to delete {}: {e}", path.display()))
+    }
+
+    /// The record for a device, if one was imported
+    pub fn get(&self, udid: &str) -> Option<PairingFile> {
+        let path = self.path_for(udid).ok()?;
+        if !path.exists() {
+            return None;
+        }
+        match PairingFile::read_from_file(&path) {
+            Ok(p) => Some(p),
+            Err(e) => {
+                log::warn!("Ignoring unreadable pairing file {}: {e:?}", path.display());
+                None
+            }
+        }
+    }
+
+    /// Every readable record, sorted by UDID
+    pub fn list(&self) -> Vec<StoredPairing> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut records: Vec<_> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|x| x == "plist"))
+            .filter_map(|path| {
+                let udid = path.file_stem()?.to_string_lossy().to_string();
+                let record = PairingFile::read_from_file(&path).ok()?;
+                Some(StoredPairing {
+                    udid,
+                    wifi_mac_address: record.wifi_mac_address,
+                    path,
+                })
+            })
+            .collect();
+        records.sort_by(|a, b| a.udid.cmp(&b.udid));
+        records
+    }
+
+    /// The record for the device with this Wi-Fi MAC address, as mDNS advertises it
+    pub fn find_by_mac(&self, mac: &str) -> Option<StoredPairing> {
+        self.list()
+            .into_iter()
+            .find(|p| p.wifi_mac_address.eq_ignore_ascii_case(mac))
+    }
+
+    /// Where a device's record goes, refusing anything that isn't a UDID so
+    /// a name like `../x` can't reach outside the store
+    fn path_for(&self, udid: &str) -> Result<PathBuf, String> {
+        if !looks_like_udid(udid) {
+            return Err(format!("{udid} isn't a UDID"));
+        }
+        Ok(self.dir.join(format!("{udid}.plist")))
+    }
+}
+
+/// UDIDs are hex digits and dashes, e.g. `00008030-001A2D3E0C38802E`
+fn looks_like_udid(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit() || c == '-')
+}
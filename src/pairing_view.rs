@@ -0,0 +1,136 @@
+// Jackson Coxson
+//! Window for importing and removing pairing files
+
+use egui::{Color32, RichText};
+use rfd::FileDialog;
+
+use crate::pairing::{PAIRING_DIR_ENV, PairingStore, StoredPairing};
+
+pub struct PairingView {
+    pub open: bool,
+    /// None when there is nowhere to keep pairing files
+    store: Option<PairingStore>,
+    records: Vec<StoredPairing>,
+    /// UDID to file the next import under, for records that don't name one
+    udid: String,
+    status: Option<Result<String, String>>,
+}
+
+impl PairingView {
+    pub fn new(store: Option<PairingStore>) -> Self {
+        let records = store.as_ref().map(PairingStore::list).unwrap_or_default();
+        Self {
+            open: false,
+            store,
+            records,
+            udid: String::new(),
+            status: None,
+        }
+    }
+
+    pub fn records(&self) -> &[StoredPairing] {
+        &self.records
+    }
+
+    fn store(&self) -> Result<&PairingStore, String> {
+        self.store
+            .as_ref()
+            .ok_or_else(|| format!("Nowhere to keep pairing files, set {PAIRING_DIR_ENV}"))
+    }
+
+    pub fn import(&mut self, path: &std::path::Path) -> Result<String, String> {
+        let udid = Some(self.udid.trim()).filter(|x| !x.is_empty());
+        let store = self.store()?;
+        let res = store.import(path, udid);
+        self.records = store.list();
+        res
+    }
+
+    pub fn delete(&mut self, udid: &str) -> Result<(), String> {
+        let store = self.store()?;
+        let res = store.delete(udid);
+        self.records = store.list();
+        res
+    }
+
+    /// Returns true when the records changed, so the device list should be refreshed
+    pub fn show(&mut self, ctx: &egui::Context, selected_udid: Option<&str>) -> bool {
+        let mut changed = false;
+        let mut open = self.open;
+        egui::Window::new("Pairing files")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.label(
+                    "Used when usbmuxd has no pairing record for a device, \
+                     and to connect to devices over Wi-Fi.",
+                );
+                match self.store() {
+                    Ok(store) => ui.label(format!("Stored in {}", store.dir().display())),
+                    Err(e) => ui.label(RichText::new(e).color(Color32::RED)),
+                };
+                ui.horizontal(|ui| {
+                    ui.label("UDID");
+                    ui.text_edit_singleline(&mut self.udid)
+                        .on_hover_text("Only needed if the pairing file doesn't name its device");
+                    if ui.button("Import...").clicked()
+                        && let Some(p) = FileDialog::new()
+                            .add_filter("Pairing file", &["plist"])
+                            .pick_file()
+                    {
+                        self.status = Some(
+                            self.import(&p)
+                                .map(|udid| format!("Imported the pairing file for {udid}")),
+                        );
+                        changed = true;
+                    }
+                });
+                match &self.status {
+                    Some(Ok(s)) => {
+                        ui.label(s);
+                    }
+                    Some(Err(e)) => {
+                        ui.label(RichText::new(e).color(Color32::RED));
+                    }
+                    None => {}
+                }
+                ui.separator();
+
+                if self.records.is_empty() {
+                    ui.label("No pairing files imported.");
+                    return;
+                }
+                let mut delete = None;
+                egui::Grid::new("pairing_grid")
+                    .striped(true)
+                    .num_columns(3)
+                    .show(ui, |ui| {
+                        ui.strong("UDID");
+                        ui.strong("Wi-Fi MAC");
+                        ui.strong("");
+                        ui.end_row();
+                        for r in &self.records {
+                            let udid = RichText::new(&r.udid).monospace();
+                            if selected_udid == Some(r.udid.as_str()) {
+                                ui.label(udid.strong()).on_hover_text("The selected device");
+                            } else {
+                                ui.label(udid);
+                            }
+                            ui.monospace(&r.wifi_mac_address);
+                            if ui.button("Delete").clicked() {
+                                delete = Some(r.udid.clone());
+                            }
+                            ui.end_row();
+                        }
+                    });
+                if let Some(udid) = delete {
+                    self.status = Some(
+                        self.delete(&udid)
+                            .map(|_| format!("Deleted the pairing file for {udid}")),
+                    );
+                    changed = true;
+                }
+            });
+        self.open = open;
+        changed
+    }
+}
@@ -0,0 +1,17 @@
+// Jackson Coxson
+//! Where the app keeps its files
+
+use std::path::PathBuf;
+
+/// Our folder in the platform's app data directory, if the platform says where that is
+pub fn data_dir() -> Option<PathBuf> {
+    let env_dir = |key: &str| std::env::var_os(key).map(PathBuf::from);
+    let base = if cfg!(windows) {
+        env_dir("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        env_dir("HOME").map(|h| h.join("Library/Application Support"))
+    } else {
+        env_dir("XDG_DATA_HOME").or_else(|| env_dir("HOME").map(|h| h.join(".local/share")))
+    };
+    Some(base?.join("ioreg_explorer"))
+}
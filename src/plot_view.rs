@@ -0,0 +1,95 @@
+// Jackson Coxson
+//! Panel plotting pinned properties over time
+
+use std::time::SystemTime;
+
+use egui::{Color32, RichText};
+use egui_plot::{Legend, Line, Plot, PlotPoints};
+use rfd::FileDialog;
+
+use crate::series::Series;
+
+#[derive(Default)]
+pub struct PlotView {
+    export_error: Option<String>,
+}
+
+impl PlotView {
+    /// Draws the plot and a row of stats per series, dropping any that get unpinned
+    pub fn show(&mut self, ui: &mut egui::Ui, series: &mut Vec<Series>) {
+        let mut unpin = None;
+        ui.horizontal(|ui| {
+            ui.heading("Pinned");
+            if ui.button("Clear history").clicked() {
+                for s in series.iter_mut() {
+                    let last = s.samples.pop();
+                    s.samples.clear();
+                    s.samples.extend(last);
+                }
+            }
+        });
+        if let Some(e) = &self.export_error {
+            ui.label(RichText::new(e).color(Color32::RED));
+        }
+        egui::Grid::new("series_stats")
+            .striped(true)
+            .show(ui, |ui| {
+                for (i, s) in series.iter().enumerate() {
+                    ui.monospace(&s.label);
+                    if let Some(stats) = s.stats() {
+                        ui.label(format!(
+                            "min {}  max {}  avg {:.2}  ({} samples)",
+                            stats.min,
+                            stats.max,
+                            stats.avg,
+                            s.samples.len()
+                        ));
+                    }
+                    if ui.button("Export CSV...").clicked()
+                        && let Some(p) = FileDialog::new()
+                            .set_can_create_directories(true)
+                            .set_file_name(format!("{}.csv", s.label.replace('/', "_")))
+                            .save_file()
+                    {
+                        self.export_error =
+                            std::fs::write(p, s.to_csv()).err().map(|e| e.to_string());
+                    }
+                    if ui.button("Unpin").clicked() {
+                        unpin = Some(i);
+                    }
+                    ui.end_row();
+                }
+            });
+        if let Some(i) = unpin {
+            series.remove(i);
+        }
+
+        // Seconds since the oldest sample, so every series shares the x axis
+        let start = series
+            .iter()
+            .filter_map(|s| s.samples.first())
+            .map(|s| s.time)
+            .min()
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        Plot::new("series_plot")
+            .legend(Legend::default())
+            .x_axis_label("Time")
+            .x_axis_formatter(|mark, _| {
+                let secs = mark.value.max(0.0) as u64;
+                format!("{}:{:02}", secs / 60, secs % 60)
+            })
+            .show(ui, |plot_ui| {
+                for s in series.iter() {
+                    let points: PlotPoints = s
+                        .samples
+                        .iter()
+                        .map(|x| {
+                            let t = x.time.duration_since(start).unwrap_or_default();
+                            [t.as_secs_f64(), x.value]
+                        })
+                        .collect();
+                    plot_ui.line(Line::new(&s.label, points));
+                }
+            });
+    }
+}
@@ -0,0 +1,125 @@
+// Jackson Coxson
+//! Following a device through a restart, shutdown or sleep
+
+use std::time::{Duration, Instant};
+
+use crate::backend::PowerAction;
+
+/// How far a power action has got
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PowerStage {
+    /// Waiting on the relay to agree
+    Sending,
+    /// The relay agreed, the device hasn't gone yet
+    Sent,
+    /// The device dropped off usbmuxd
+    Disconnected,
+    /// The device restarted and came back, or shut down or slept as asked
+    Done,
+    Failed(String),
+}
+
+/// A power action in progress, updated from the device list as the device
+/// detaches and re-attaches
+#[derive(Debug, Clone)]
+pub struct PowerProgress {
+    pub udid: String,
+    pub action: PowerAction,
+    pub stage: PowerStage,
+    started: Instant,
+    /// When the device dropped off, for how long a restart kept it away
+    disconnected: Option<Instant>,
+    finished: Option<Instant>,
+}
+
+impl PowerProgress {
+    pub fn new(udid: impl Into<String>, action: PowerAction) -> Self {
+        Self {
+            udid: udid.into(),
+            action,
+            stage: PowerStage::Sending,
+            started: Instant::now(),
+            disconnected: None,
+            finished: None,
+        }
+    }
+
+    /// The relay agreed to the action
+    pub fn sent(&mut self) {
+        if self.stage != PowerStage::Sending {
+            return;
+        }
+        if self.action.detaches() {
+            self.stage = PowerStage::Sent;
+        } else {
+            self.finish(PowerStage::Done);
+        }
+    }
+
+    /// The request failed
+    ///
+    /// Ignored once the device has gone, as it may drop the connection before answering.
+    pub fn failed(&mut self, error: String) {
+        if matches!(self.stage, PowerStage::Sending | PowerStage::Sent) {
+            self.finish(PowerStage::Failed(error));
+        }
+    }
+
+    /// Notes whether usbmuxd lists the device, returning whether the stage changed
+    pub fn update(&mut self, attached: bool) -> bool {
+        let stage = match (&self.stage, attached) {
+            (PowerStage::Sending | PowerStage::Sent, false) if self.action.detaches() => {
+                self.disconnected = Some(Instant::now());
+                match self.action {
+                    PowerAction::Restart => PowerStage::Disconnected,
+                    _ => PowerStage::Done,
+                }
+            }
+            (PowerStage::Disconnected, true) => PowerStage::Done,
+            _ => return false,
+        };
+        if stage == PowerStage::Done {
+            self.finish(stage);
+        } else {
+            self.stage = stage;
+        }
+        true
+    }
+
+    fn finish(&mut self, stage: PowerStage) {
+        self.stage = stage;
+        self.finished = Some(Instant::now());
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.stage, PowerStage::Done | PowerStage::Failed(_))
+    }
+
+    /// Time since the action was asked for, until it finished
+    pub fn elapsed(&self) -> Duration {
+        let end = self.finished.unwrap_or_else(Instant::now);
+        end.duration_since(self.started)
+    }
+
+    /// A line saying where things are, e.g. `Restarting, waiting for it to come back (12s)`
+    pub fn describe(&self) -> String {
+        let secs = self.elapsed().as_secs();
+        match (&self.stage, self.action) {
+            (PowerStage::Sending, a) => format!("Sending {}...", a.name().to_lowercase()),
+            (PowerStage::Sent, _) => format!("Waiting for the device to disconnect ({secs}s)"),
+            (PowerStage::Disconnected, _) => {
+                format!("Restarting, waiting for it to come back ({secs}s)")
+            }
+            (PowerStage::Done, PowerAction::Restart) => {
+                let down = match (self.disconnected, self.finished) {
+                    (Some(d), Some(f)) => format!(", {}s offline", f.duration_since(d).as_secs()),
+                    _ => String::new(),
+                };
+                format!("Restarted after {secs}s{down}")
+            }
+            (PowerStage::Done, PowerAction::Shutdown) => "Shut down".to_string(),
+            (PowerStage::Done, PowerAction::Sleep) => "Put to sleep".to_string(),
+            (PowerStage::Failed(e), a) => format!("{} failed: {e}", a.name()),
+        }
+    }
+}
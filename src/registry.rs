@@ -0,0 +1,81 @@
+// Jackson Coxson
+//! Helpers for walking the plist dictionaries the diagnostics relay returns
+
+/// Key holding an entry's child entries
+pub const CHILDREN_KEY: &str = "IORegistryEntryChildren";
+
+/// Key on the root entry listing the planes the registry has
+pub const PLANES_KEY: &str = "IORegistryPlanes";
+
+/// Planes worth offering before a device has listed its own
+pub const KNOWN_PLANES: [&str; 4] = ["IOService", "IODeviceTree", "IOPower", "IOUSB"];
+
+/// One step from a parent node to a child node
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// Location of a node, relative to the root entry
+pub type NodePath = Vec<PathSegment>;
+
+/// Appends a segment to a copy of `path`
+pub fn child_path(path: &NodePath, segment: PathSegment) -> NodePath {
+    let mut p = path.clone();
+    p.push(segment);
+    p
+}
+
+/// Name shown for an entry
+pub fn entry_name(entry: &plist::Dictionary) -> &str {
+    entry
+        .get("IORegistryEntryName")
+        .and_then(|x| x.as_string())
+        .unwrap_or("Root")
+}
+
+/// Class shown for an entry
+pub fn entry_class(entry: &plist::Dictionary) -> &str {
+    entry
+        .get("IOObjectClass")
+        .or_else(|| entry.get("IOClass"))
+        .and_then(|x| x.as_string())
+        .unwrap_or("?")
+}
+
+/// The planes a root entry lists, sorted
+pub fn registry_planes(root: &plist::Dictionary) -> Vec<String> {
+    let mut planes: Vec<String> = match root.get(PLANES_KEY) {
+        // Plane names map to themselves
+        Some(plist::Value::Dictionary(d)) => d.keys().cloned().collect(),
+        Some(plist::Value::Array(a)) => a
+            .iter()
+            .filter_map(|x| x.as_string())
+            .map(ToString::to_string)
+            .collect(),
+        _ => Vec::new(),
+    };
+    planes.sort();
+    planes
+}
+
+/// Formats a value for a single line, summarizing containers
+pub fn format_value(value: &plist::Value) -> String {
+    match value {
+        plist::Value::Array(a) => format!("[{} items]", a.len()),
+        plist::Value::Dictionary(d) => format!("{{{} items}}", d.len()),
+        plist::Value::Boolean(b) => b.to_string(),
+        plist::Value::Data(d) => {
+            let hex: String = d.iter().take(32).map(|b| format!("{b:02x}")).collect();
+            let more = if d.len() > 32 { "…" } else { "" };
+            format!("<{hex}{more}> ({} bytes)", d.len())
+        }
+        plist::Value::Date(d) => d.to_xml_format(),
+        plist::Value::Real(r) => r.to_string(),
+        plist::Value::Integer(i) => i.to_string(),
+        plist::Value::String(s) => format!("{s:?}"),
+        plist::Value::Uid(u) => format!("UID({})", u.get()),
+        _ => "?".to_string(),
+    }
+}
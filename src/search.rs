@@ -0,0 +1,160 @@
+// Jackson Coxson
+//! Searching a registry for keys, values, entry names and classes
+
+use crate::{
+    model::{Registry, RegistryEntry},
+    registry::{NodePath, PathSegment, child_path},
+};
+
+/// What part of a node matched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchKind {
+    EntryName,
+    EntryClass,
+    Key,
+    Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub path: NodePath,
+    pub kind: MatchKind,
+}
+
+/// A parsed search string
+///
+/// Plain text matches keys, string and number values, entry names and classes.
+/// `key=value` only matches properties whose key contains `key` and whose value
+/// contains `value`. Matching is case-insensitive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    key: Option<String>,
+    text: String,
+}
+
+impl Query {
+    /// Parses a search string, returning `None` if there is nothing to search for
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        if s.is_empty() {
+            return None;
+        }
+        Some(match s.split_once('=') {
+            Some((key, value)) => Self {
+                key: Some(key.trim().to_lowercase()),
+                text: value.trim().to_lowercase(),
+            },
+            None => Self {
+                key: None,
+                text: s.to_lowercase(),
+            },
+        })
+    }
+}
+
+/// Finds every match in `reg`, in the order the tree shows them
+pub fn search(reg: &Registry, query: &Query) -> Vec<Match> {
+    let mut matches = Vec::new();
+    for (_, entry) in reg.entries() {
+        search_entry(entry, query, &mut matches);
+    }
+    matches
+}
+
+fn contains(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(needle)
+}
+
+/// Text of a leaf value a search can match against
+fn searchable(value: &plist::Value) -> Option<String> {
+    match value {
+        plist::Value::String(s) => Some(s.clone()),
+        plist::Value::Integer(i) => Some(i.to_string()),
+        plist::Value::Real(r) => Some(r.to_string()),
+        plist::Value::Boolean(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn search_entry(entry: &RegistryEntry, query: &Query, out: &mut Vec<Match>) {
+    let path = &entry.node_path;
+    if query.key.is_none() {
+        if contains(&entry.name, &query.text) {
+            out.push(Match {
+                path: path.clone(),
+                kind: MatchKind::EntryName,
+            });
+        } else if contains(&entry.class, &query.text)
+            || entry
+                .properties
+                .get("IOClass")
+                .and_then(|x| x.as_string())
+                .is_some_and(|c| contains(c, &query.text))
+        {
+            out.push(Match {
+                path: path.clone(),
+                kind: MatchKind::EntryClass,
+            });
+        }
+    }
+
+    for (k, v) in &entry.properties {
+        let p = child_path(path, PathSegment::Key(k.clone()));
+        search_property(k, v, p, query, out);
+    }
+}
+
+fn search_property(
+    key: &str,
+    value: &plist::Value,
+    path: NodePath,
+    query: &Query,
+    out: &mut Vec<Match>,
+) {
+    match &query.key {
+        Some(k) => {
+            if contains(key, k) && searchable(value).is_some_and(|v| contains(&v, &query.text)) {
+                out.push(Match {
+                    path: path.clone(),
+                    kind: MatchKind::Value,
+                });
+            }
+        }
+        None => {
+            // Names and classes were already matched on the entry itself
+            let is_identity = matches!(key, "IORegistryEntryName" | "IOObjectClass" | "IOClass");
+            if contains(key, &query.text) {
+                out.push(Match {
+                    path: path.clone(),
+                    kind: MatchKind::Key,
+                });
+            } else if !is_identity && searchable(value).is_some_and(|v| contains(&v, &query.text)) {
+                out.push(Match {
+                    path: path.clone(),
+                    kind: MatchKind::Value,
+                });
+            }
+        }
+    }
+
+    match value {
+        plist::Value::Dictionary(d) => {
+            for (k, v) in d {
+                search_property(
+                    k,
+                    v,
+                    child_path(&path, PathSegment::Key(k.clone())),
+                    query,
+                    out,
+                );
+            }
+        }
+        plist::Value::Array(a) => {
+            for (i, v) in a.iter().enumerate() {
+                // Array items have no key of their own to match
+                search_property("", v, child_path(&path, PathSegment::Index(i)), query, out);
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,107 @@
+// Jackson Coxson
+//! History of pinned numeric properties across polls
+
+use std::time::SystemTime;
+
+use crate::{model::Registry, registry::NodePath};
+
+/// The value of a node as a number, if it is one
+pub fn numeric(value: &plist::Value) -> Option<f64> {
+    match value {
+        plist::Value::Integer(i) => i
+            .as_signed()
+            .map(|x| x as f64)
+            .or_else(|| i.as_unsigned().map(|x| x as f64)),
+        plist::Value::Real(r) => Some(*r),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    pub time: SystemTime,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+}
+
+/// Every value a pinned property has had while watching
+#[derive(Debug, Clone)]
+pub struct Series {
+    pub path: NodePath,
+    pub label: String,
+    pub samples: Vec<Sample>,
+}
+
+impl Series {
+    /// Pins the node at `path`, if it is a number
+    pub fn pin(reg: &Registry, path: &NodePath, time: SystemTime) -> Option<Self> {
+        let value = numeric(reg.lookup(path)?)?;
+        Some(Self {
+            path: path.clone(),
+            label: reg.path_label(path),
+            samples: vec![Sample { time, value }],
+        })
+    }
+
+    /// Records the value from a new poll, skipping it if the node is gone or not a number
+    pub fn record(&mut self, reg: &Registry, time: SystemTime) -> bool {
+        match reg.lookup(&self.path).and_then(numeric) {
+            Some(value) => {
+                self.samples.push(Sample { time, value });
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn stats(&self) -> Option<Stats> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut stats = Stats {
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            avg: 0.0,
+        };
+        for s in &self.samples {
+            stats.min = stats.min.min(s.value);
+            stats.max = stats.max.max(s.value);
+            stats.avg += s.value;
+        }
+        stats.avg /= self.samples.len() as f64;
+        Some(stats)
+    }
+
+    /// The samples as CSV, with both an ISO 8601 and a Unix timestamp per row
+    pub fn to_csv(&self) -> String {
+        let mut out = format!("timestamp,unix_time,{}\n", csv_field(&self.label));
+        for s in &self.samples {
+            let unix = s
+                .time
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64();
+            out.push_str(&format!(
+                "{},{unix:.3},{}\n",
+                plist::Date::from(s.time).to_xml_format(),
+                s.value
+            ));
+        }
+        out
+    }
+}
+
+/// Quotes a CSV field if it needs it, doubling any quotes inside (RFC 4180)
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
@@ -0,0 +1,534 @@
+// Jackson Coxson
+//! Collapsible tree view of an IORegistry dictionary
+//!
+//! The tree is flattened into rows for whatever is currently expanded, and only
+//! the rows scrolled into view are drawn, so huge registries stay responsive.
+
+use std::collections::HashSet;
+
+use egui::{Color32, RichText};
+
+use crate::{
+    model::{ELIDED, EntryId, Registry},
+    registry::{NodePath, PathSegment, child_path, format_value},
+    search::Match,
+    series::numeric,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowKind {
+    /// A registry entry, i.e. a dictionary in the children tree
+    Entry,
+    /// A property of an entry, or an item nested inside one
+    Property,
+}
+
+/// A single visible line of the tree
+#[derive(Debug, Clone)]
+pub struct Row {
+    pub depth: usize,
+    pub path: NodePath,
+    pub kind: RowKind,
+    /// Entry name or property key
+    pub label: String,
+    /// Entry class or formatted property value
+    pub value: String,
+    pub expandable: bool,
+    /// Whether this row is a search match
+    pub highlighted: bool,
+    /// Whether this row, or something collapsed inside it, changed in the last poll
+    pub changed: bool,
+    /// Whether this is a number that can be pinned for plotting
+    pub numeric: bool,
+    /// Whether this is a `Data` blob that can be inspected
+    pub data: bool,
+}
+
+/// Something a click in the tree asks the app to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeAction {
+    /// Pin or unpin a number
+    Pin(NodePath),
+    /// Open a `Data` property in the inspector
+    Inspect(NodePath),
+}
+
+pub struct RegistryTree {
+    expanded: HashSet<NodePath>,
+    rows: Vec<Row>,
+    dirty: bool,
+
+    // Search
+    highlights: HashSet<NodePath>,
+    /// Strict ancestors of every match
+    match_ancestors: HashSet<NodePath>,
+    filter: bool,
+    current: Option<NodePath>,
+    scroll_to: Option<NodePath>,
+
+    // Watch
+    changed: HashSet<NodePath>,
+    changed_ancestors: HashSet<NodePath>,
+
+    // Navigation
+    /// The entry whose path the breadcrumbs show
+    selected: Option<NodePath>,
+    goto: String,
+    goto_error: Option<String>,
+}
+
+impl Default for RegistryTree {
+    fn default() -> Self {
+        // The root entry starts out open
+        Self {
+            expanded: HashSet::from([Vec::new()]),
+            rows: Vec::new(),
+            dirty: true,
+            highlights: HashSet::new(),
+            match_ancestors: HashSet::new(),
+            filter: false,
+            current: None,
+            scroll_to: None,
+            changed: HashSet::new(),
+            changed_ancestors: HashSet::new(),
+            selected: None,
+            goto: String::new(),
+            goto_error: None,
+        }
+    }
+}
+
+impl RegistryTree {
+    /// Marks the rows stale, e.g. after the registry was replaced
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn is_expanded(&self, path: &NodePath) -> bool {
+        self.expanded.contains(path)
+    }
+
+    pub fn toggle(&mut self, path: &NodePath) {
+        if !self.expanded.remove(path) {
+            self.expanded.insert(path.clone());
+        }
+        self.dirty = true;
+    }
+
+    pub fn expand_all(&mut self, reg: &Registry) {
+        for (_, entry) in reg.entries() {
+            collect_expandable_dict(
+                &entry.properties,
+                entry.node_path.clone(),
+                &mut self.expanded,
+            );
+        }
+        self.dirty = true;
+    }
+
+    pub fn collapse_all(&mut self) {
+        self.expanded.clear();
+        self.dirty = true;
+    }
+
+    /// Highlights search matches, optionally hiding every branch without one
+    pub fn set_matches(&mut self, matches: &[Match], filter: bool) {
+        self.highlights = matches.iter().map(|m| m.path.clone()).collect();
+        self.match_ancestors.clear();
+        for m in matches {
+            for i in 0..m.path.len() {
+                self.match_ancestors.insert(m.path[..i].to_vec());
+            }
+        }
+        self.filter = filter && !matches.is_empty();
+        self.current = self.current.take().filter(|c| self.highlights.contains(c));
+        self.dirty = true;
+    }
+
+    /// Marks the nodes that changed since the previous poll
+    pub fn set_changed(&mut self, changed: &[NodePath]) {
+        self.changed = changed.iter().cloned().collect();
+        self.changed_ancestors.clear();
+        for p in changed {
+            for i in 0..p.len() {
+                self.changed_ancestors.insert(p[..i].to_vec());
+            }
+        }
+        self.dirty = true;
+    }
+
+    /// Expands everything above `path` and scrolls it into view
+    pub fn reveal(&mut self, path: &NodePath) {
+        self.current = Some(path.clone());
+        self.bring_into_view(path);
+    }
+
+    fn bring_into_view(&mut self, path: &NodePath) {
+        for i in 0..path.len() {
+            self.expanded.insert(path[..i].to_vec());
+        }
+        self.scroll_to = Some(path.clone());
+        self.dirty = true;
+    }
+
+    /// Selects an entry row, whose path the breadcrumbs then show
+    pub fn select(&mut self, path: &NodePath) {
+        self.selected = Some(path.clone());
+        self.dirty = true;
+    }
+
+    pub fn selected(&self) -> Option<&NodePath> {
+        self.selected.as_ref()
+    }
+
+    /// The path "Copy path" gives for the selected entry, which [`Self::go_to`] takes back
+    pub fn selected_path(&self, reg: &Registry) -> Option<String> {
+        let id = reg.entry_at(self.selected.as_ref()?)?;
+        Some(reg.path(id))
+    }
+
+    /// Selects the entry at a registry path such as
+    /// `IOService:/AppleARMPE/arm-io@10F00000` and scrolls to it
+    pub fn go_to(&mut self, reg: &Registry, path: &str) -> Result<EntryId, String> {
+        let id = reg
+            .find(path)
+            .ok_or_else(|| format!("No entry at {}", path.trim()))?;
+        let node = reg[id].node_path.clone();
+        self.select(&node);
+        self.bring_into_view(&node);
+        Ok(id)
+    }
+
+    /// The rows currently visible, rebuilt if anything changed since the last call
+    pub fn rows(&mut self, reg: &Registry) -> &[Row] {
+        if self.dirty {
+            let mut rows = std::mem::take(&mut self.rows);
+            rows.clear();
+            Flatten {
+                tree: self,
+                reg,
+                rows: &mut rows,
+            }
+            .entry(Registry::ROOT, 0, false);
+            self.rows = rows;
+            self.dirty = false;
+        }
+        &self.rows
+    }
+
+    /// Draws the tree, returning what a click on a row's button asked for
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        reg: &Registry,
+        pinned: &[NodePath],
+    ) -> Option<TreeAction> {
+        ui.horizontal(|ui| {
+            if ui.button("Expand all").clicked() {
+                self.expand_all(reg);
+            }
+            if ui.button("Collapse all").clicked() {
+                self.collapse_all();
+            }
+            ui.separator();
+            let input = ui.add(
+                egui::TextEdit::singleline(&mut self.goto)
+                    .hint_text("IOService:/AppleARMPE/arm-io@10F00000")
+                    .desired_width(320.0),
+            );
+            if input.changed() {
+                self.goto_error = None;
+            }
+            let entered = input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if (ui.button("Go to").clicked() || entered) && !self.goto.trim().is_empty() {
+                let path = self.goto.clone();
+                self.goto_error = self.go_to(reg, &path).err();
+            }
+            if let Some(e) = &self.goto_error {
+                ui.label(RichText::new(e).color(Color32::RED));
+            }
+        });
+        self.breadcrumbs(ui, reg);
+
+        let row_height = ui.spacing().interact_size.y;
+        let indent = ui.spacing().indent;
+        let total_rows = self.rows(reg).len();
+
+        let mut scroll_area = egui::ScrollArea::both().auto_shrink([false, false]);
+        if let Some(target) = self.scroll_to.take()
+            && let Some(i) = self.rows.iter().position(|r| r.path == target)
+        {
+            let spacing = ui.spacing().item_spacing.y;
+            scroll_area = scroll_area.vertical_scroll_offset(i as f32 * (row_height + spacing));
+        }
+
+        let mut toggled = None;
+        let mut selected = None;
+        let mut action = None;
+        scroll_area.show_rows(ui, row_height, total_rows, |ui, range| {
+            for row in &self.rows[range] {
+                ui.horizontal(|ui| {
+                    ui.add_space(row.depth as f32 * indent);
+                    if row.expandable {
+                        let icon = if self.expanded.contains(&row.path) {
+                            "⏷"
+                        } else {
+                            "⏵"
+                        };
+                        if ui.add(egui::Button::new(icon).frame(false)).clicked() {
+                            toggled = Some(row.path.clone());
+                        }
+                    } else {
+                        ui.add_space(row_height);
+                    }
+
+                    let (label, value) = match row.kind {
+                        RowKind::Entry => (
+                            RichText::new(&row.label).strong(),
+                            RichText::new(format!("<class {}>", row.value)).color(Color32::GRAY),
+                        ),
+                        RowKind::Property => (
+                            RichText::new(format!("{}:", row.label)).monospace(),
+                            RichText::new(&row.value).monospace(),
+                        ),
+                    };
+                    let label = if self.current.as_ref() == Some(&row.path) {
+                        label
+                            .background_color(Color32::from_rgb(255, 140, 0))
+                            .color(Color32::BLACK)
+                    } else if row.highlighted {
+                        label
+                            .background_color(Color32::from_rgb(255, 220, 0))
+                            .color(Color32::BLACK)
+                    } else {
+                        label
+                    };
+                    if row.kind == RowKind::Entry {
+                        let is_selected = self.selected.as_ref() == Some(&row.path);
+                        let response = ui.selectable_label(is_selected, label);
+                        if response.clicked() {
+                            selected = Some(row.path.clone());
+                        }
+                        response.context_menu(|ui| {
+                            if ui.button("Copy path").clicked() {
+                                if let Some(id) = reg.entry_at(&row.path) {
+                                    ui.ctx().copy_text(reg.path(id));
+                                }
+                                ui.close();
+                            }
+                        });
+                    } else {
+                        ui.label(label);
+                    }
+                    let value = if row.changed {
+                        value
+                            .background_color(Color32::from_rgb(120, 200, 120))
+                            .color(Color32::BLACK)
+                    } else {
+                        value
+                    };
+                    ui.label(value);
+
+                    if row.numeric {
+                        let is_pinned = pinned.contains(&row.path);
+                        let button = egui::Button::new(if is_pinned { "📌" } else { "📍" })
+                            .frame(false)
+                            .selected(is_pinned);
+                        let hover = if is_pinned { "Unpin" } else { "Pin to plot" };
+                        if ui.add(button).on_hover_text(hover).clicked() {
+                            action = Some(TreeAction::Pin(row.path.clone()));
+                        }
+                    }
+                    if row.data {
+                        let button = egui::Button::new("🔍").frame(false);
+                        if ui.add(button).on_hover_text("Inspect").clicked() {
+                            action = Some(TreeAction::Inspect(row.path.clone()));
+                        }
+                    }
+                });
+            }
+        });
+
+        if let Some(path) = toggled {
+            self.toggle(&path);
+        }
+        if let Some(path) = selected {
+            self.select(&path);
+        }
+        action
+    }
+
+    /// The selected entry's path, each step of which selects the entry there
+    fn breadcrumbs(&mut self, ui: &mut egui::Ui, reg: &Registry) {
+        let Some(id) = self.selected.as_ref().and_then(|p| reg.entry_at(p)) else {
+            return;
+        };
+        let mut steps: Vec<EntryId> = reg.ancestors(id).collect();
+        steps.reverse();
+
+        let mut clicked = None;
+        ui.horizontal_wrapped(|ui| {
+            ui.spacing_mut().item_spacing.x = 2.0;
+            let whole_plane = reg.is_plane_root();
+            if !whole_plane {
+                // Nothing above a queried entry to go to
+                let plane = reg.plane().map(|p| format!("{p}:")).unwrap_or_default();
+                ui.label(RichText::new(format!("{plane}/{ELIDED}/")).monospace());
+            }
+            for (i, step) in steps.iter().enumerate() {
+                let text = match (i, reg.plane()) {
+                    (0, Some(plane)) if whole_plane => format!("{plane}:"),
+                    _ => reg[*step].label(),
+                };
+                if i > 0 {
+                    ui.label("/");
+                }
+                let link = ui.link(RichText::new(text).monospace());
+                if link.on_hover_text(&reg[*step].class).clicked() {
+                    clicked = Some(*step);
+                }
+            }
+            ui.add_space(8.0);
+            if ui.small_button("Copy path").clicked()
+                && let Some(path) = self.selected_path(reg)
+            {
+                ui.ctx().copy_text(path);
+            }
+        });
+
+        if let Some(step) = clicked {
+            let node = reg[step].node_path.clone();
+            self.select(&node);
+            self.bring_into_view(&node);
+        }
+    }
+}
+
+struct Flatten<'a> {
+    tree: &'a RegistryTree,
+    reg: &'a Registry,
+    rows: &'a mut Vec<Row>,
+}
+
+impl Flatten<'_> {
+    /// Whether a node is shown at all, given the search filter
+    ///
+    /// `inside` is set below a matched node, whose contents are always shown.
+    fn visible(&self, path: &NodePath, inside: bool) -> bool {
+        !self.tree.filter
+            || inside
+            || self.tree.highlights.contains(path)
+            || self.tree.match_ancestors.contains(path)
+    }
+
+    fn is_open(&self, path: &NodePath) -> bool {
+        self.tree.expanded.contains(path)
+            || (self.tree.filter && self.tree.match_ancestors.contains(path))
+    }
+
+    fn changed(&self, path: &NodePath, open: bool) -> bool {
+        self.tree.changed.contains(path) || (!open && self.tree.changed_ancestors.contains(path))
+    }
+
+    fn entry(&mut self, id: EntryId, depth: usize, inside: bool) {
+        let entry = &self.reg[id];
+        let path = &entry.node_path;
+        if !self.visible(path, inside) {
+            return;
+        }
+        let highlighted = self.tree.highlights.contains(path);
+        let open = self.is_open(path);
+        self.rows.push(Row {
+            depth,
+            path: path.clone(),
+            kind: RowKind::Entry,
+            label: entry.name.clone(),
+            value: entry.class.clone(),
+            expandable: true,
+            highlighted,
+            changed: self.changed(path, open),
+            numeric: false,
+            data: false,
+        });
+        if !open {
+            return;
+        }
+
+        let inside = inside || highlighted;
+        for (k, v) in &entry.properties {
+            let p = child_path(path, PathSegment::Key(k.clone()));
+            self.property(k, v, p, depth + 1, inside);
+        }
+        for &c in &entry.children {
+            self.entry(c, depth + 1, inside);
+        }
+    }
+
+    fn property(
+        &mut self,
+        label: &str,
+        value: &plist::Value,
+        path: NodePath,
+        depth: usize,
+        inside: bool,
+    ) {
+        if !self.visible(&path, inside) {
+            return;
+        }
+        let highlighted = self.tree.highlights.contains(&path);
+        let expandable = matches!(value, plist::Value::Array(_) | plist::Value::Dictionary(_));
+        let open = expandable && self.is_open(&path);
+        self.rows.push(Row {
+            depth,
+            path: path.clone(),
+            kind: RowKind::Property,
+            label: label.to_string(),
+            value: format_value(value),
+            expandable,
+            highlighted,
+            changed: self.changed(&path, open),
+            numeric: numeric(value).is_some(),
+            data: matches!(value, plist::Value::Data(_)),
+        });
+        if !open {
+            return;
+        }
+
+        let inside = inside || highlighted;
+        match value {
+            plist::Value::Dictionary(d) => {
+                for (k, v) in d {
+                    let p = child_path(&path, PathSegment::Key(k.clone()));
+                    self.property(k, v, p, depth + 1, inside);
+                }
+            }
+            plist::Value::Array(a) => {
+                for (i, v) in a.iter().enumerate() {
+                    let p = child_path(&path, PathSegment::Index(i));
+                    self.property(&format!("[{i}]"), v, p, depth + 1, inside);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn collect_expandable(value: &plist::Value, path: NodePath, out: &mut HashSet<NodePath>) {
+    match value {
+        plist::Value::Dictionary(d) => collect_expandable_dict(d, path, out),
+        plist::Value::Array(a) => {
+            for (i, v) in a.iter().enumerate() {
+                collect_expandable(v, child_path(&path, PathSegment::Index(i)), out);
+            }
+            out.insert(path);
+        }
+        _ => {}
+    }
+}
+
+fn collect_expandable_dict(d: &plist::Dictionary, path: NodePath, out: &mut HashSet<NodePath>) {
+    for (k, v) in d {
+        collect_expandable(v, child_path(&path, PathSegment::Key(k.clone())), out);
+    }
+    out.insert(path);
+}
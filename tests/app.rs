@@ -0,0 +1,568 @@
+// Jackson Coxson
+#![cfg(feature = "gui")]
+
+mod common;
+
+use std::time::Duration;
+
+use common::{
+    FIXTURES,
+    mock::{phone, spawn},
+    registry,
+};
+use ioreg_explorer::{
+    app::{MyApp, Tab},
+    backend::{
+        PowerAction, PowerOptions,
+        mock::{MockBackend, MockDevice, MockFault},
+    },
+    commands::{self, GuiCommands, IdeviceCommands, explain_error},
+    decode::DecodingStore,
+    export::{DataEncoding, ExportFormat, export},
+    pairing::PairingStore,
+    power::PowerStage,
+    registry::PathSegment,
+};
+use tokio::sync::mpsc::unbounded_channel;
+
+/// Selects a listed device by name, as picked from the combo box
+fn select(app: &mut MyApp, name: &str) {
+    let key = app
+        .devices()
+        .unwrap()
+        .iter()
+        .find(|(_, d)| d.name == name)
+        .map(|(k, _)| k.clone())
+        .unwrap();
+    app.select_device(&key);
+}
+
+/// Polls the app until `done` holds
+async fn poll_until(app: &mut MyApp, done: impl Fn(&MyApp) -> bool) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            app.poll_backend();
+            if done(app) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("app never reached the expected state");
+}
+
+#[tokio::test]
+async fn app_debounces_typing_and_drops_stale_answers() {
+    let (gui_sender, gui_recv) = unbounded_channel();
+    let (idevice_sender, idevice_receiver) = unbounded_channel();
+    tokio::spawn(commands::run(
+        MockBackend::new([phone()]),
+        idevice_receiver,
+        gui_sender.clone(),
+    ));
+    let mut app = MyApp::new(gui_recv, idevice_sender.clone());
+    idevice_sender.send(IdeviceCommands::GetDevices).unwrap();
+    poll_until(&mut app, |a| a.devices().is_some()).await;
+    select(&mut app, "Test iPhone");
+
+    for class in ["IOPM", "IOPMPower", "IOPMPowerSource"] {
+        app.edit_query("IOService", "", class);
+        assert!(app.poll_debounce().is_some());
+    }
+    assert_eq!(app.latest_request(), None);
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while app.poll_debounce().is_some() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    // Only the final text was sent
+    assert_eq!(app.latest_request(), Some(0));
+    poll_until(&mut app, |a| a.current_ioregistry().is_some()).await;
+
+    // An answer to an older request doesn't replace the current one
+    gui_sender
+        .send(GuiCommands::IORegistry(7, Some(plist::Dictionary::new())))
+        .unwrap();
+    app.poll_backend();
+    assert!(
+        app.current_ioregistry()
+            .unwrap()
+            .root()
+            .properties
+            .contains_key("InstantAmperage")
+    );
+}
+
+#[tokio::test]
+async fn app_follows_the_selection_flow() {
+    let (gui_sender, gui_recv) = unbounded_channel();
+    let (idevice_sender, idevice_receiver) = unbounded_channel();
+    tokio::spawn(commands::run(
+        MockBackend::new([phone()]),
+        idevice_receiver,
+        gui_sender,
+    ));
+    let mut app = MyApp::new(gui_recv, idevice_sender.clone());
+    assert!(app.devices().is_none());
+    assert_eq!(app.devices_placeholder(), "Loading...");
+
+    idevice_sender.send(IdeviceCommands::GetDevices).unwrap();
+    poll_until(&mut app, |a| a.devices().is_some()).await;
+
+    select(&mut app, "Test iPhone");
+    assert!(app.device_info().is_none());
+    poll_until(&mut app, |a| a.device_info().is_some()).await;
+    let info = app.device_info().unwrap();
+    assert_eq!(info[0], ("Device Name".into(), "Test iPhone".into()));
+    assert_eq!(info[1], ("Model".into(), "iPhone12,1".into()));
+
+    app.set_query("IOService", "", "IOPMPowerSource");
+    app.send_query();
+    poll_until(&mut app, |a| a.current_ioregistry().is_some()).await;
+    assert!(
+        app.current_ioregistry()
+            .unwrap()
+            .root()
+            .properties
+            .contains_key("InstantAmperage")
+    );
+}
+
+#[tokio::test]
+async fn app_tabs_show_other_diagnostics() {
+    let mut gas_gauge = plist::Dictionary::new();
+    gas_gauge.insert("CycleCount".into(), 412.into());
+    let device = phone()
+        .with_diagnostics("GasGauge", gas_gauge)
+        .with_gestalt("ProductType", "iPhone12,1")
+        .with_gestalt("ChipID", 32816);
+    let (sender, recv) = spawn(MockBackend::new([device]));
+    let mut app = MyApp::new(recv, sender.clone());
+    sender.send(IdeviceCommands::GetDevices).unwrap();
+    poll_until(&mut app, |a| a.devices().is_some()).await;
+    select(&mut app, "Test iPhone");
+
+    app.set_tab(Tab::GasGauge);
+    poll_until(&mut app, |a| a.current_ioregistry().is_some()).await;
+    app.set_search("CycleCount=412", false);
+    assert_eq!(app.matches().len(), 1);
+
+    // Switching away drops the old answer rather than showing it under the new tab
+    app.edit_gestalt_keys("ProductType, SerialNumber");
+    app.set_tab(Tab::MobileGestalt);
+    assert!(app.current_ioregistry().is_none());
+    poll_until(&mut app, |a| a.current_ioregistry().is_some()).await;
+    let gestalt = app.current_ioregistry().unwrap();
+    assert_eq!(
+        gestalt.root().properties.keys().collect::<Vec<_>>(),
+        ["ProductType"]
+    );
+}
+
+#[tokio::test]
+async fn app_offers_the_device_planes_and_seen_classes() {
+    let device = phone().with_ioregistry(None, None, None, registry("ioregistry_root.plist"));
+    let (sender, recv) = spawn(MockBackend::new([
+        device,
+        MockDevice::new("udid-2", 2, "iPad"),
+    ]));
+    let mut app = MyApp::new(recv, sender.clone());
+    assert!(app.planes().contains(&"IODeviceTree".to_string()));
+    sender.send(IdeviceCommands::GetDevices).unwrap();
+    poll_until(&mut app, |a| a.devices().is_some()).await;
+
+    select(&mut app, "Test iPhone");
+    poll_until(&mut app, |a| {
+        a.planes().contains(&"IOAccessory".to_string())
+    })
+    .await;
+    assert_eq!(app.planes().len(), 6);
+
+    // A device whose root lists no planes keeps the usual ones
+    select(&mut app, "iPad");
+    assert!(!app.planes().contains(&"IOAccessory".to_string()));
+
+    select(&mut app, "Test iPhone");
+    app.set_query("IOService", "", "IOPMPowerSource");
+    app.send_query();
+    poll_until(&mut app, |a| a.current_ioregistry().is_some()).await;
+    assert_eq!(app.classes(), ["AppleARMPMUCharger"]);
+}
+
+#[tokio::test]
+async fn app_completes_from_fetched_planes() {
+    let device = phone().with_ioregistry(
+        Some("IOService"),
+        None,
+        None,
+        registry("ioregistry_tree.plist"),
+    );
+    let (sender, recv) = spawn(MockBackend::new([device]));
+    let mut app = MyApp::new(recv, sender.clone());
+    sender.send(IdeviceCommands::GetDevices).unwrap();
+    poll_until(&mut app, |a| a.devices().is_some()).await;
+    select(&mut app, "Test iPhone");
+
+    app.set_query("IOService", "", "");
+    app.send_query();
+    poll_until(&mut app, |a| a.current_ioregistry().is_some()).await;
+    assert!(app.entry_names().contains(&"usb-drd0"));
+    assert_eq!(app.match_count(), None);
+
+    // Narrower queries still complete from the whole plane, and say what they'd find
+    app.set_query("IOService", "", "IOPMPowerSource");
+    assert_eq!(app.match_count(), Some(0));
+    app.set_query("IOService", "", "AppleARMIODevice");
+    assert_eq!(app.match_count(), Some(3));
+    app.send_query();
+    poll_until(&mut app, |a| a.current_ioregistry().is_none()).await;
+    assert!(app.classes().contains(&"AppleARMPMUCharger"));
+
+    // Nothing is known about other planes
+    app.set_query("IODeviceTree", "pmu", "");
+    assert_eq!(app.match_count(), None);
+}
+
+#[tokio::test]
+async fn app_follows_a_restart() {
+    let backend = MockBackend::new([phone()]);
+    let (sender, recv) = spawn(backend.clone());
+    let mut app = MyApp::new(recv, sender.clone());
+    sender.send(IdeviceCommands::GetDevices).unwrap();
+    poll_until(&mut app, |a| a.devices().is_some()).await;
+    select(&mut app, "Test iPhone");
+
+    let options = PowerOptions {
+        wait_for_disconnect: true,
+        show_notification: false,
+    };
+    app.power(PowerAction::Restart, options);
+    assert_eq!(app.power_progress().unwrap().stage, PowerStage::Sending);
+    poll_until(&mut app, |a| a.is_disconnected()).await;
+    assert_eq!(
+        app.power_progress().unwrap().stage,
+        PowerStage::Disconnected
+    );
+    poll_until(&mut app, |a| a.power_progress().unwrap().is_finished()).await;
+    assert_eq!(app.power_progress().unwrap().stage, PowerStage::Done);
+    assert!(!app.is_disconnected());
+    assert_eq!(
+        backend.power_requests(),
+        [(
+            "00008030-001A2D3E0C38802E".to_string(),
+            PowerAction::Restart,
+            options
+        )]
+    );
+}
+
+#[tokio::test]
+async fn app_shows_power_failures() {
+    let backend = MockBackend::new([phone()]);
+    let (sender, recv) = spawn(backend.clone());
+    let mut app = MyApp::new(recv, sender.clone());
+    sender.send(IdeviceCommands::GetDevices).unwrap();
+    poll_until(&mut app, |a| a.devices().is_some()).await;
+    select(&mut app, "Test iPhone");
+
+    backend.set_device_fault("00008030-001A2D3E0C38802E", Some(MockFault::DeviceLocked));
+    app.power(PowerAction::Shutdown, PowerOptions::default());
+    poll_until(&mut app, |a| a.power_progress().unwrap().is_finished()).await;
+    assert!(matches!(
+        app.power_progress().unwrap().stage,
+        PowerStage::Failed(_)
+    ));
+    assert!(backend.power_requests().is_empty());
+}
+
+#[tokio::test]
+async fn app_shows_usbmuxd_failure() {
+    let backend = MockBackend::new([]);
+    backend.set_fault(Some(MockFault::NoUsbmuxd));
+    let (gui_sender, gui_recv) = unbounded_channel();
+    let (idevice_sender, idevice_receiver) = unbounded_channel();
+    tokio::spawn(commands::run(backend, idevice_receiver, gui_sender));
+    let mut app = MyApp::new(gui_recv, idevice_sender.clone());
+
+    idevice_sender.send(IdeviceCommands::GetDevices).unwrap();
+    poll_until(&mut app, |a| a.devices_placeholder() != "Loading...").await;
+    assert!(
+        app.devices_placeholder()
+            .starts_with("Failed to connect to usbmuxd!")
+    );
+    assert!(app.devices().is_none());
+}
+
+#[tokio::test]
+async fn app_diffs_two_live_captures() {
+    let backend = MockBackend::new([phone()]);
+    let (gui_sender, gui_recv) = unbounded_channel();
+    let (idevice_sender, idevice_receiver) = unbounded_channel();
+    tokio::spawn(commands::run(backend.clone(), idevice_receiver, gui_sender));
+    let mut app = MyApp::new(gui_recv, idevice_sender.clone());
+
+    idevice_sender.send(IdeviceCommands::GetDevices).unwrap();
+    poll_until(&mut app, |a| a.devices().is_some()).await;
+    select(&mut app, "Test iPhone");
+    app.set_query("IOService", "", "IOPMPowerSource");
+
+    app.capture_snapshot(0);
+    poll_until(&mut app, |a| a.diff().snapshot(0).is_some()).await;
+    assert!(app.diff().changes().is_none());
+
+    // Unplug the charger
+    let mut charging = registry("ioregistry_power_source.plist");
+    charging.insert("IsCharging".into(), false.into());
+    charging.insert("InstantAmperage".into(), 1500.into());
+    backend.update_device(
+        MockDevice::new("00008030-001A2D3E0C38802E", 1, "Test iPhone").with_ioregistry(
+            Some("IOService"),
+            None,
+            Some("IOPMPowerSource"),
+            charging,
+        ),
+    );
+
+    app.capture_snapshot(1);
+    poll_until(&mut app, |a| a.diff().changes().is_some()).await;
+    let changes = app.diff().changes().unwrap();
+    assert_eq!(changes.len(), 2);
+    let amperage = changes
+        .iter()
+        .find(|c| c.path_string().ends_with("/InstantAmperage"))
+        .unwrap();
+    assert_eq!(amperage.new, Some(1500.into()));
+
+    // A query the device has nothing for fails the capture rather than dropping it
+    app.set_query("IOService", "", "IOUSBHostDevice");
+    app.capture_snapshot(0);
+    poll_until(&mut app, |a| a.diff().error().is_some()).await;
+    assert_eq!(
+        app.diff().error(),
+        Some("The device returned no registry for this query")
+    );
+    assert!(app.diff().snapshot(0).is_some());
+}
+
+#[tokio::test]
+async fn app_watch_highlights_changes() {
+    let backend = MockBackend::new([phone()]);
+    let (gui_sender, gui_recv) = unbounded_channel();
+    let (idevice_sender, idevice_receiver) = unbounded_channel();
+    tokio::spawn(commands::run(backend.clone(), idevice_receiver, gui_sender));
+    let mut app = MyApp::new(gui_recv, idevice_sender.clone());
+
+    idevice_sender.send(IdeviceCommands::GetDevices).unwrap();
+    poll_until(&mut app, |a| a.devices().is_some()).await;
+    select(&mut app, "Test iPhone");
+    app.set_query("IOService", "", "IOPMPowerSource");
+    app.set_watch(true, Duration::from_millis(20));
+
+    // The first poll has nothing to compare against
+    app.poll_watch();
+    poll_until(&mut app, |a| a.current_ioregistry().is_some()).await;
+    assert!(app.changed().is_empty());
+    let amperage = vec![PathSegment::Key("InstantAmperage".into())];
+    app.toggle_pin(&amperage);
+    assert_eq!(app.series()[0].label, "InstantAmperage");
+
+    let mut reg = registry("ioregistry_power_source.plist");
+    reg.insert("InstantAmperage".into(), (-300).into());
+    backend.update_device(
+        MockDevice::new("00008030-001A2D3E0C38802E", 1, "Test iPhone").with_ioregistry(
+            Some("IOService"),
+            None,
+            Some("IOPMPowerSource"),
+            reg,
+        ),
+    );
+
+    // Stands in for the repaints the GUI would schedule
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while app.changed().is_empty() {
+            app.poll_watch();
+            app.poll_backend();
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("no change was highlighted");
+    assert_eq!(app.changed(), [amperage]);
+    let samples = &app.series()[0].samples;
+    assert_eq!(samples.last().unwrap().value, -300.0);
+}
+
+#[tokio::test]
+async fn app_tells_same_named_devices_apart() {
+    let (sender, recv) = spawn(MockBackend::new([
+        MockDevice::new("udid-1", 1, "iPhone"),
+        MockDevice::new("udid-ïïïïïd", 2, "iPhone"),
+    ]));
+    let mut app = MyApp::new(recv, sender.clone());
+    sender.send(IdeviceCommands::GetDevices).unwrap();
+    poll_until(&mut app, |a| a.devices().is_some()).await;
+
+    let key = app
+        .devices()
+        .unwrap()
+        .keys()
+        .find(|k| k.udid != "udid-1")
+        .cloned()
+        .unwrap();
+    app.select_device(&key);
+    assert_eq!(app.selected_label(), "iPhone (USB) [ïïïïïd]");
+}
+
+#[tokio::test]
+async fn app_keeps_files_only_in_the_stores_it_is_given() {
+    let (sender, recv) = spawn(MockBackend::new([]));
+    let app = MyApp::new(recv, sender);
+    assert_eq!(app.inspector().store().path(), None);
+    assert!(app.pairing().records().is_empty());
+
+    let dir = std::env::temp_dir().join(format!("stores_{}", uuid::Uuid::new_v4()));
+    let pairing = PairingStore::new(dir.join("pairing"));
+    pairing
+        .import(format!("{FIXTURES}/pairing_file.plist").as_ref(), None)
+        .unwrap();
+    let decodings = dir.join("decodings.plist");
+    let (sender, recv) = spawn(MockBackend::new([]));
+    let app = MyApp::new(recv, sender)
+        .with_pairing_store(pairing)
+        .with_decoding_store(DecodingStore::load(&decodings));
+    assert_eq!(app.inspector().store().path(), Some(decodings.as_path()));
+    assert_eq!(app.pairing().records().len(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn app_opens_dumps_without_a_device() {
+    let (gui_sender, gui_recv) = unbounded_channel();
+    let (idevice_sender, _idevice_receiver) = unbounded_channel();
+    let mut app = MyApp::new(gui_recv, idevice_sender);
+
+    let path = std::env::temp_dir().join(format!("ioreg_{}.json", uuid::Uuid::new_v4()));
+    let reg = registry("ioregistry_power_source.plist");
+    std::fs::write(
+        &path,
+        export(
+            &reg.clone().into(),
+            ExportFormat::Json,
+            DataEncoding::Base64,
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    app.open_file(&path).unwrap();
+    assert_eq!(app.opened_file(), Some(path.as_path()));
+    assert_eq!(
+        app.current_ioregistry()
+            .unwrap()
+            .root()
+            .properties
+            .get("CycleCount"),
+        reg.get("CycleCount")
+    );
+
+    app.set_search("Temperature", false);
+    assert_eq!(app.matches().len(), 1);
+
+    // A late answer from before the file was opened doesn't replace it
+    gui_sender
+        .send(GuiCommands::IORegistry(0, Some(plist::Dictionary::new())))
+        .unwrap();
+    app.poll_backend();
+    assert!(
+        app.current_ioregistry()
+            .unwrap()
+            .root()
+            .properties
+            .contains_key("CycleCount")
+    );
+
+    app.set_export_format(ExportFormat::BinaryPlist, DataEncoding::Base64);
+    let saved = path.with_extension("plist");
+    app.save_to(&saved).unwrap();
+    let back: plist::Dictionary = plist::from_file(&saved).unwrap();
+    assert_eq!(back.get("CycleCount"), reg.get("CycleCount"));
+
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&saved).unwrap();
+    assert!(app.open_file(&path).is_err());
+}
+
+#[tokio::test]
+async fn app_follows_unplugged_devices() {
+    let backend = MockBackend::new([phone()]);
+    let (gui_sender, gui_recv) = unbounded_channel();
+    let (idevice_sender, idevice_receiver) = unbounded_channel();
+    tokio::spawn(commands::run(backend.clone(), idevice_receiver, gui_sender));
+    let mut app = MyApp::new(gui_recv, idevice_sender.clone());
+
+    idevice_sender.send(IdeviceCommands::GetDevices).unwrap();
+    poll_until(&mut app, |a| a.devices().is_some()).await;
+    select(&mut app, "Test iPhone");
+    poll_until(&mut app, |a| a.device_info().is_some()).await;
+    assert!(!app.is_disconnected());
+
+    // No refresh is asked for, the unplug alone updates the list
+    backend.remove_device("00008030-001A2D3E0C38802E");
+    poll_until(&mut app, |a| a.is_disconnected()).await;
+    assert!(app.devices().unwrap().is_empty());
+    assert!(app.device_info().is_none());
+
+    backend.add_device(phone());
+    poll_until(&mut app, |a| a.device_info().is_some()).await;
+    assert!(!app.is_disconnected());
+    assert_eq!(app.selected_device().unwrap().device_id, 1);
+}
+
+#[tokio::test]
+async fn app_shows_errors_until_a_retry_succeeds() {
+    let backend = MockBackend::new([phone()]);
+    let (gui_sender, gui_recv) = unbounded_channel();
+    let (idevice_sender, idevice_receiver) = unbounded_channel();
+    tokio::spawn(commands::run(backend.clone(), idevice_receiver, gui_sender));
+    let mut app = MyApp::new(gui_recv, idevice_sender.clone());
+
+    idevice_sender.send(IdeviceCommands::GetDevices).unwrap();
+    poll_until(&mut app, |a| a.devices().is_some()).await;
+    backend.set_device_fault("00008030-001A2D3E0C38802E", Some(MockFault::DeviceLocked));
+    select(&mut app, "Test iPhone");
+    poll_until(&mut app, |a| a.device_error().is_some()).await;
+    assert!(app.device_info().is_none());
+
+    app.set_query("IOService", "", "IOPMPowerSource");
+    app.send_query();
+    poll_until(&mut app, |a| a.query_error().is_some()).await;
+    let explanation = explain_error(app.query_error().unwrap());
+    assert!(explanation.starts_with("Device locked."), "{explanation}");
+
+    // Unlocking and retrying clears both
+    backend.set_device_fault("00008030-001A2D3E0C38802E", None);
+    app.send_query();
+    poll_until(&mut app, |a| a.current_ioregistry().is_some()).await;
+    assert!(app.query_error().is_none());
+    let key = app.selected_key().unwrap().clone();
+    app.select_device(&key);
+    poll_until(&mut app, |a| a.device_info().is_some()).await;
+    assert!(app.device_error().is_none());
+}
+
+#[tokio::test]
+async fn app_survives_the_task_going_away() {
+    let (gui_sender, gui_recv) = unbounded_channel();
+    let (idevice_sender, idevice_receiver) = unbounded_channel();
+    let mut app = MyApp::new(gui_recv, idevice_sender);
+    drop((gui_sender, idevice_receiver));
+
+    app.poll_backend();
+    assert!(app.backend_error().is_some());
+    app.open_file(format!("{FIXTURES}/ioregistry_power_source.plist").as_ref())
+        .unwrap();
+    app.send_query();
+}
@@ -0,0 +1,194 @@
+// Jackson Coxson
+
+mod common;
+
+use std::process::Stdio;
+
+use common::{FIXTURES, FakeDaemon, FakeDevice, registry};
+use idevice::usbmuxd::UsbmuxdAddr;
+use tokio::process::Command;
+
+/// Runs the binary against the fake daemon, returning (success, stdout, stderr)
+async fn cli(daemon: &FakeDaemon, args: &[&str]) -> (bool, String, String) {
+    let addr = match daemon.addr() {
+        UsbmuxdAddr::TcpSocket(a) => a.to_string(),
+        #[cfg(unix)]
+        UsbmuxdAddr::UnixSocket(p) => p,
+    };
+    let out = Command::new(env!("CARGO_BIN_EXE_ioreg_explorer"))
+        .args(args)
+        .env("USBMUXD_SOCKET_ADDRESS", addr)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .unwrap();
+    (
+        out.status.success(),
+        String::from_utf8(out.stdout).unwrap(),
+        String::from_utf8(out.stderr).unwrap(),
+    )
+}
+
+#[tokio::test]
+async fn lists_devices() {
+    let daemon = FakeDaemon::start(vec![FakeDevice::recorded()]).await;
+    let (ok, stdout, _) = cli(&daemon, &["devices"]).await;
+    assert!(ok);
+    assert_eq!(stdout, "00008030-001A2D3E0C38802E\tUSB\tTest iPhone\n");
+}
+
+#[tokio::test]
+async fn prints_device_info() {
+    let daemon = FakeDaemon::start(vec![FakeDevice::recorded()]).await;
+    let (ok, stdout, _) = cli(&daemon, &["info"]).await;
+    assert!(ok);
+    assert!(stdout.contains("Model: iPhone12,1\n"));
+}
+
+#[tokio::test]
+async fn queries_to_a_file() {
+    let daemon = FakeDaemon::start(vec![FakeDevice::recorded()]).await;
+    let path = std::env::temp_dir().join(format!("ioreg_{}.txt", uuid::Uuid::new_v4()));
+    let (ok, stdout, _) = cli(
+        &daemon,
+        &[
+            "query",
+            "--plane",
+            "IOService",
+            "--class",
+            "IOPMPowerSource",
+            "-o",
+            path.to_str().unwrap(),
+        ],
+    )
+    .await;
+    assert!(ok);
+    assert!(stdout.is_empty());
+
+    let written = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(written.contains("BatterySerialNumber"));
+}
+
+#[tokio::test]
+async fn fails_on_unknown_udid() {
+    let daemon = FakeDaemon::start(vec![FakeDevice::recorded()]).await;
+    let (ok, _, stderr) = cli(&daemon, &["query", "--udid", "nope"]).await;
+    assert!(!ok);
+    assert_eq!(stderr, "No device with UDID nope is connected\n");
+}
+
+#[tokio::test]
+async fn diffs_two_files() {
+    let fixture = format!("{FIXTURES}/ioregistry_power_source.plist");
+    let mut reg = registry("ioregistry_power_source.plist");
+    reg.insert("ExternalConnected".into(), false.into());
+    let path = std::env::temp_dir().join(format!("ioreg_{}.plist", uuid::Uuid::new_v4()));
+    plist::to_file_binary(&path, &reg).unwrap();
+
+    let out = Command::new(env!("CARGO_BIN_EXE_ioreg_explorer"))
+        .args(["diff", &fixture, path.to_str().unwrap()])
+        .output()
+        .await
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(out.status.success());
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert_eq!(
+        stdout,
+        "~ AppleARMPMUCharger/ExternalConnected: true -> false\n"
+    );
+}
+
+#[tokio::test]
+async fn queries_as_json() {
+    let daemon = FakeDaemon::start(vec![FakeDevice::recorded()]).await;
+    let (ok, stdout, _) = cli(
+        &daemon,
+        &[
+            "query",
+            "--plane",
+            "IOService",
+            "--class",
+            "IOPMPowerSource",
+            "--format",
+            "json",
+        ],
+    )
+    .await;
+    assert!(ok);
+    let json: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["BatterySerialNumber"], "F8Y0472VJ4NLTVKAV");
+}
+
+#[tokio::test]
+async fn restarts_and_follows_the_device_back() {
+    let daemon = FakeDaemon::start(vec![FakeDevice::recorded()]).await;
+    let (ok, _, stderr) = cli(&daemon, &["power", "restart"]).await;
+    assert!(!ok);
+    assert!(stderr.ends_with("Cancelled\n"));
+    assert!(daemon.power_requests().is_empty());
+
+    let (ok, stdout, _) = cli(
+        &daemon,
+        &["power", "restart", "--yes", "--notify", "--follow"],
+    )
+    .await;
+    assert!(ok);
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines[0], "Restart sent to 00008030-001A2D3E0C38802E");
+    assert!(lines[1].starts_with("Restarting, waiting for it to come back"));
+    assert!(lines[2].starts_with("Restarted after"));
+
+    let req = &daemon.power_requests()[0];
+    assert_eq!(req["Request"].as_string(), Some("Restart"));
+    assert_eq!(req["DisplayPass"].as_boolean(), Some(true));
+    assert_eq!(req["WaitForDisconnect"].as_boolean(), Some(false));
+}
+
+#[tokio::test]
+async fn manages_pairing_files() {
+    let dir = std::env::temp_dir().join(format!("pairing_{}", uuid::Uuid::new_v4()));
+    let cli = async |args: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_ioreg_explorer"))
+            .args(args)
+            .env("IOREG_EXPLORER_PAIRING_DIR", &dir)
+            .output()
+            .await
+            .unwrap();
+        (out.status.success(), String::from_utf8(out.stdout).unwrap())
+    };
+    let fixture = format!("{FIXTURES}/pairing_file.plist");
+
+    let (ok, stdout) = cli(&["pairing", "import", &fixture]).await;
+    assert!(ok);
+    assert_eq!(
+        stdout,
+        "Imported the pairing file for 00008030-001A2D3E0C38802E\n"
+    );
+
+    let (ok, stdout) = cli(&["pairing", "list"]).await;
+    assert!(ok);
+    assert!(stdout.starts_with("00008030-001A2D3E0C38802E\ta4:83:e7:12:34:56\t"));
+
+    assert!(
+        cli(&["pairing", "delete", "00008030-001A2D3E0C38802E"])
+            .await
+            .0
+    );
+    assert_eq!(cli(&["pairing", "list"]).await, (true, String::new()));
+
+    // Only UDIDs name files in the store
+    let outside = dir.with_extension("plist");
+    std::fs::copy(&fixture, &outside).unwrap();
+    let escape = format!("../{}", dir.file_name().unwrap().to_string_lossy());
+    assert!(!cli(&["pairing", "delete", &escape]).await.0);
+    assert!(outside.exists());
+    std::fs::remove_file(&outside).unwrap();
+    assert!(
+        !cli(&["pairing", "delete", "00008030-001A2D3E0C38802E"])
+            .await
+            .0
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
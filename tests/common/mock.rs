@@ -0,0 +1,44 @@
+// Jackson Coxson
+//! Helpers for driving the command loop against [`MockBackend`]
+
+use std::time::Duration;
+
+use ioreg_explorer::{
+    backend::mock::{MockBackend, MockDevice},
+    commands::{self, GuiCommands, IdeviceCommands},
+};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+
+use super::registry;
+
+pub fn phone() -> MockDevice {
+    MockDevice::new("00008030-001A2D3E0C38802E", 1, "Test iPhone")
+        .with_value("ProductType", "iPhone12,1")
+        .with_value("ProductVersion", "17.5.1")
+        .with_value("BuildVersion", "21F90")
+        .with_ioregistry(
+            Some("IOService"),
+            None,
+            Some("IOPMPowerSource"),
+            registry("ioregistry_power_source.plist"),
+        )
+}
+
+pub fn spawn(
+    backend: MockBackend,
+) -> (
+    UnboundedSender<IdeviceCommands>,
+    UnboundedReceiver<GuiCommands>,
+) {
+    let (gui_sender, gui_recv) = unbounded_channel();
+    let (idevice_sender, idevice_receiver) = unbounded_channel();
+    tokio::spawn(commands::run(backend, idevice_receiver, gui_sender));
+    (idevice_sender, gui_recv)
+}
+
+pub async fn next(gui_recv: &mut UnboundedReceiver<GuiCommands>) -> GuiCommands {
+    tokio::time::timeout(Duration::from_secs(5), gui_recv.recv())
+        .await
+        .expect("timed out waiting for the idevice task")
+        .expect("idevice task exited")
+}
@@ -0,0 +1,558 @@
+// Jackson Coxson
+//! A stand-in for usbmuxd and the lockdownd/diagnostics_relay services behind it
+//!
+//! Speaks the real wire protocols, so the usbmuxd backend can be exercised
+//! end-to-end. Responses come from the recorded fixtures in `tests/fixtures`.
+
+#![allow(dead_code)]
+
+pub mod mock;
+
+use std::{
+    io,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use idevice::usbmuxd::UsbmuxdAddr;
+use rustls::{
+    ServerConfig,
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    task::JoinHandle,
+};
+use tokio_rustls::TlsAcceptor;
+
+pub const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+/// A registry dump from the fixtures, e.g. `ioregistry_tree.plist`
+pub fn registry(name: &str) -> plist::Dictionary {
+    plist::from_file(format!("{FIXTURES}/{name}")).unwrap()
+}
+
+const LOCKDOWND_PORT: u16 = 62078;
+const RELAY_PORT: u16 = 49152;
+const RELAY_SERVICE: &str = "com.apple.mobile.diagnostics_relay";
+
+/// The arguments of an `IORegistry` request
+pub type RegistryQuery = (Option<String>, Option<String>, Option<String>);
+
+/// A device attached to the fake muxer
+#[derive(Debug, Clone)]
+pub struct FakeDevice {
+    pub udid: String,
+    pub device_id: u32,
+    /// Lockdown values, as returned by `GetValue` with no key
+    pub values: plist::Dictionary,
+    /// Canned relay responses keyed by (plane, entry, class)
+    pub registries: Vec<(RegistryQuery, plist::Dictionary)>,
+    /// Other diagnostics keyed by domain, as answered to `All`
+    pub diagnostics: plist::Dictionary,
+    /// MobileGestalt values, or `None` to refuse them like iOS 17.4 and later
+    pub gestalt: Option<plist::Dictionary>,
+    /// Raw pairing record handed out by `ReadPairRecord`, none if empty
+    pub pair_record: Vec<u8>,
+}
+
+impl FakeDevice {
+    /// The device the fixtures were recorded from
+    pub fn recorded() -> Self {
+        let values: plist::Dictionary =
+            plist::from_file(format!("{FIXTURES}/lockdown_values.plist")).unwrap();
+        let power_source = registry("ioregistry_power_source.plist");
+        Self {
+            udid: values["UniqueDeviceID"].as_string().unwrap().to_string(),
+            device_id: 1,
+            values,
+            registries: vec![(
+                (
+                    Some("IOService".into()),
+                    None,
+                    Some("IOPMPowerSource".into()),
+                ),
+                power_source,
+            )],
+            diagnostics: plist::from_file(format!("{FIXTURES}/diagnostics_all.plist")).unwrap(),
+            gestalt: Some(plist::from_file(format!("{FIXTURES}/mobilegestalt.plist")).unwrap()),
+            pair_record: std::fs::read(format!("{FIXTURES}/pairing_file.plist")).unwrap(),
+        }
+    }
+}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+struct State {
+    devices: Mutex<Vec<FakeDevice>>,
+    tls: TlsAcceptor,
+    /// Attached and Detached messages for `Listen` connections
+    events: tokio::sync::broadcast::Sender<plist::Dictionary>,
+    /// Restart, Shutdown and Sleep requests the relay received
+    power_requests: Mutex<Vec<plist::Dictionary>>,
+}
+
+/// A running fake usbmuxd, stopped when dropped
+pub struct FakeDaemon {
+    addr: UsbmuxdAddr,
+    state: Arc<State>,
+    task: JoinHandle<()>,
+}
+
+impl FakeDaemon {
+    /// Listens on a random localhost TCP port
+    pub async fn start(devices: Vec<FakeDevice>) -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = UsbmuxdAddr::TcpSocket(listener.local_addr().unwrap());
+        let state = State::new(devices);
+        let s = state.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve_muxer(s.clone(), Box::new(stream)));
+            }
+        });
+        Self { addr, state, task }
+    }
+
+    /// Listens on a fresh Unix socket in the temp dir
+    #[cfg(unix)]
+    pub async fn start_unix(devices: Vec<FakeDevice>) -> Self {
+        let path = std::env::temp_dir().join(format!("fake_usbmuxd_{}", uuid::Uuid::new_v4()));
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let addr = UsbmuxdAddr::UnixSocket(path.to_string_lossy().into_owned());
+        let state = State::new(devices);
+        let s = state.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve_muxer(s.clone(), Box::new(stream)));
+            }
+        });
+        Self { addr, state, task }
+    }
+
+    /// Where `UsbmuxdAddr` should point to reach this daemon
+    pub fn addr(&self) -> UsbmuxdAddr {
+        self.addr.clone()
+    }
+
+    pub fn attach(&self, device: FakeDevice) {
+        self.state.attach(device);
+    }
+
+    pub fn detach(&self, udid: &str) {
+        self.state.detach(udid);
+    }
+
+    /// Restart, Shutdown and Sleep requests received so far
+    pub fn power_requests(&self) -> Vec<plist::Dictionary> {
+        self.state.power_requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for FakeDaemon {
+    fn drop(&mut self) {
+        self.task.abort();
+        #[cfg(unix)]
+        if let UsbmuxdAddr::UnixSocket(path) = &self.addr {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// A device reachable directly over TCP, as one on Wi-Fi is, stopped when dropped
+pub struct FakeNetworkDevice {
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl FakeNetworkDevice {
+    /// Listens on the real lockdownd and relay ports, so needs an address of its own
+    pub async fn start(ip: IpAddr, device: FakeDevice) -> Self {
+        let state = State::new(vec![device.clone()]);
+        let lockdown = tokio::net::TcpListener::bind((ip, LOCKDOWND_PORT))
+            .await
+            .unwrap();
+        let relay = tokio::net::TcpListener::bind((ip, RELAY_PORT))
+            .await
+            .unwrap();
+
+        let (d, s) = (device.clone(), state.clone());
+        let lockdown = tokio::spawn(async move {
+            while let Ok((stream, _)) = lockdown.accept().await {
+                let (state, d) = (state.clone(), d.clone());
+                tokio::spawn(async move { serve_lockdown(&state, d, Box::new(stream)).await });
+            }
+        });
+        let relay = tokio::spawn(async move {
+            while let Ok((stream, _)) = relay.accept().await {
+                tokio::spawn(serve_relay(s.clone(), device.clone(), Box::new(stream)));
+            }
+        });
+        Self {
+            tasks: vec![lockdown, relay],
+        }
+    }
+}
+
+impl Drop for FakeNetworkDevice {
+    fn drop(&mut self) {
+        for t in &self.tasks {
+            t.abort();
+        }
+    }
+}
+
+impl State {
+    fn new(devices: Vec<FakeDevice>) -> Arc<Self> {
+        let cert = CertificateDer::from_pem_file(format!("{FIXTURES}/device_cert.pem")).unwrap();
+        let key = PrivateKeyDer::from_pem_file(format!("{FIXTURES}/device_key.pem")).unwrap();
+        let config = ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::aws_lc_rs::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert], key)
+        .unwrap();
+
+        Arc::new(Self {
+            devices: Mutex::new(devices),
+            tls: TlsAcceptor::from(Arc::new(config)),
+            events: tokio::sync::broadcast::channel(16).0,
+            power_requests: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn attach(&self, device: FakeDevice) {
+        let _ = self.events.send(attached(&device));
+        self.devices.lock().unwrap().push(device);
+    }
+
+    fn detach(&self, udid: &str) {
+        let mut devices = self.devices.lock().unwrap();
+        for d in devices.iter().filter(|d| d.udid == udid) {
+            let mut msg = plist::Dictionary::new();
+            msg.insert("MessageType".into(), "Detached".into());
+            msg.insert("DeviceID".into(), d.device_id.into());
+            let _ = self.events.send(msg);
+        }
+        devices.retain(|d| d.udid != udid);
+    }
+
+    fn device(&self, device_id: u64) -> Option<FakeDevice> {
+        self.devices
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|d| d.device_id as u64 == device_id)
+            .cloned()
+    }
+}
+
+async fn serve_muxer(state: Arc<State>, mut stream: Box<dyn Stream>) -> io::Result<()> {
+    loop {
+        let (tag, req) = read_mux_packet(&mut stream).await?;
+        let mut res = plist::Dictionary::new();
+        match req.get("MessageType").and_then(|x| x.as_string()) {
+            Some("ListDevices") => {
+                let list = state
+                    .devices
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|d| plist::Value::Dictionary(attached(d)))
+                    .collect::<Vec<_>>();
+                res.insert("DeviceList".into(), list.into());
+            }
+            Some("Listen") => {
+                let mut events = state.events.subscribe();
+                res.insert("MessageType".into(), "Result".into());
+                res.insert("Number".into(), 0.into());
+                write_mux_packet(&mut stream, tag, res).await?;
+                // Like usbmuxd, report what is already attached first
+                let current: Vec<_> = state.devices.lock().unwrap().iter().map(attached).collect();
+                for msg in current {
+                    write_mux_packet(&mut stream, 0, msg).await?;
+                }
+                while let Ok(msg) = events.recv().await {
+                    write_mux_packet(&mut stream, 0, msg).await?;
+                }
+                return Ok(());
+            }
+            Some("ReadPairRecord") => {
+                let udid = req.get("PairRecordID").and_then(|x| x.as_string());
+                let devices = state.devices.lock().unwrap();
+                match devices
+                    .iter()
+                    .find(|d| Some(d.udid.as_str()) == udid && !d.pair_record.is_empty())
+                {
+                    Some(d) => {
+                        res.insert(
+                            "PairRecordData".into(),
+                            plist::Value::Data(d.pair_record.clone()),
+                        );
+                    }
+                    None => {
+                        res.insert("MessageType".into(), "Result".into());
+                        res.insert("Number".into(), 2.into());
+                    }
+                }
+            }
+            Some("ReadBUID") => {
+                res.insert("BUID".into(), "6F1E4C2A-8B3D-4E5F-A1B2-C3D4E5F60718".into());
+            }
+            Some("Connect") => {
+                let device_id = req
+                    .get("DeviceID")
+                    .and_then(|x| x.as_unsigned_integer())
+                    .unwrap_or_default();
+                // The port is sent in network byte order
+                let port = req
+                    .get("PortNumber")
+                    .and_then(|x| x.as_unsigned_integer())
+                    .map(|p| u16::from_be(p as u16))
+                    .unwrap_or_default();
+
+                let device = state.device(device_id);
+                let number = match (&device, port) {
+                    (None, _) => 2,
+                    (Some(_), LOCKDOWND_PORT | RELAY_PORT) => 0,
+                    (Some(_), _) => 3,
+                };
+                res.insert("MessageType".into(), "Result".into());
+                res.insert("Number".into(), number.into());
+                write_mux_packet(&mut stream, tag, res).await?;
+
+                // From here on the socket belongs to the device service
+                return match (device, port) {
+                    (Some(d), LOCKDOWND_PORT) => serve_lockdown(&state, d, stream).await,
+                    (Some(d), RELAY_PORT) => serve_relay(state, d, stream).await,
+                    _ => Ok(()),
+                };
+            }
+            _ => {
+                res.insert("MessageType".into(), "Result".into());
+                res.insert("Number".into(), 1.into());
+            }
+        }
+        write_mux_packet(&mut stream, tag, res).await?;
+    }
+}
+
+fn attached(d: &FakeDevice) -> plist::Dictionary {
+    let mut props = plist::Dictionary::new();
+    props.insert("ConnectionType".into(), "USB".into());
+    props.insert("DeviceID".into(), d.device_id.into());
+    props.insert("SerialNumber".into(), d.udid.clone().into());
+    let mut dev = plist::Dictionary::new();
+    dev.insert("DeviceID".into(), d.device_id.into());
+    dev.insert("MessageType".into(), "Attached".into());
+    dev.insert("Properties".into(), props.into());
+    dev
+}
+
+async fn serve_lockdown(
+    state: &State,
+    device: FakeDevice,
+    mut stream: Box<dyn Stream>,
+) -> io::Result<()> {
+    let mut in_session = false;
+    loop {
+        let req = read_frame(&mut stream).await?;
+        let request = req
+            .get("Request")
+            .and_then(|x| x.as_string())
+            .unwrap_or_default()
+            .to_string();
+
+        let mut res = plist::Dictionary::new();
+        match request.as_str() {
+            "QueryType" => {
+                res.insert("Type".into(), "com.apple.mobile.lockdown".into());
+            }
+            "GetValue" => match req.get("Key").and_then(|x| x.as_string()) {
+                Some(key) => match device.values.get(key) {
+                    Some(v) => {
+                        res.insert("Key".into(), key.into());
+                        res.insert("Value".into(), v.clone());
+                    }
+                    None => {
+                        res.insert("Error".into(), "MissingValue".into());
+                    }
+                },
+                None => {
+                    res.insert("Value".into(), device.values.clone().into());
+                }
+            },
+            "StartSession" => {
+                res.insert("Request".into(), request.into());
+                res.insert("EnableSessionSSL".into(), true.into());
+                res.insert("SessionID".into(), uuid::Uuid::new_v4().to_string().into());
+                write_frame(&mut stream, res).await?;
+
+                stream = Box::new(state.tls.accept(stream).await?);
+                in_session = true;
+                continue;
+            }
+            "StartService" if !in_session => {
+                res.insert("Error".into(), "SessionInactive".into());
+            }
+            "StartService" => match req.get("Service").and_then(|x| x.as_string()) {
+                Some(RELAY_SERVICE) => {
+                    res.insert("Service".into(), RELAY_SERVICE.into());
+                    res.insert("Port".into(), RELAY_PORT.into());
+                }
+                _ => {
+                    res.insert("Error".into(), "InvalidService".into());
+                }
+            },
+            _ => {
+                res.insert("Error".into(), "InvalidRequest".into());
+            }
+        }
+        res.insert("Request".into(), request.into());
+        write_frame(&mut stream, res).await?;
+    }
+}
+
+async fn serve_relay(
+    state: Arc<State>,
+    device: FakeDevice,
+    mut stream: Box<dyn Stream>,
+) -> io::Result<()> {
+    loop {
+        let req = read_frame(&mut stream).await?;
+        let mut res = plist::Dictionary::new();
+        match req.get("Request").and_then(|x| x.as_string()) {
+            Some("IORegistry") => {
+                let field = |k: &str| {
+                    req.get(k)
+                        .and_then(|x| x.as_string())
+                        .map(ToString::to_string)
+                };
+                let query = (
+                    field("CurrentPlane"),
+                    field("EntryName"),
+                    field("EntryClass"),
+                );
+
+                let mut diagnostics = plist::Dictionary::new();
+                if let Some((_, r)) = device.registries.iter().find(|(q, _)| *q == query) {
+                    diagnostics.insert("IORegistry".into(), r.clone().into());
+                }
+                res.insert("Status".into(), "Success".into());
+                res.insert("Diagnostics".into(), diagnostics.into());
+            }
+            Some("MobileGestalt") => {
+                let keys: Vec<_> = req
+                    .get("MobileGestaltKeys")
+                    .and_then(|x| x.as_array())
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|k| k.as_string())
+                    .collect();
+                let mut gestalt = plist::Dictionary::new();
+                match &device.gestalt {
+                    Some(g) => {
+                        for (k, v) in g.iter().filter(|(k, _)| keys.contains(&k.as_str())) {
+                            gestalt.insert(k.clone(), v.clone());
+                        }
+                        gestalt.insert("Status".into(), "MobileGestaltSuccess".into());
+                    }
+                    None => {
+                        gestalt.insert("Status".into(), "MobileGestaltDeprecated".into());
+                    }
+                }
+                let mut diagnostics = plist::Dictionary::new();
+                diagnostics.insert("MobileGestalt".into(), gestalt.into());
+                res.insert("Status".into(), "Success".into());
+                res.insert("Diagnostics".into(), diagnostics.into());
+            }
+            Some("All") => {
+                res.insert("Status".into(), "Success".into());
+                res.insert("Diagnostics".into(), device.diagnostics.clone().into());
+            }
+            Some(domain @ ("GasGauge" | "WiFi" | "NAND")) => {
+                let diagnostics: plist::Dictionary = device
+                    .diagnostics
+                    .iter()
+                    .filter(|(k, _)| *k == domain)
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect();
+                res.insert("Status".into(), "Success".into());
+                res.insert("Diagnostics".into(), diagnostics.into());
+            }
+            Some(action @ ("Restart" | "Shutdown" | "Sleep")) => {
+                state.power_requests.lock().unwrap().push(req.clone());
+                res.insert("Status".into(), "Success".into());
+                write_frame(&mut stream, res).await?;
+                if action != "Sleep" {
+                    // The device goes away once it has answered, and comes back if restarting
+                    let restart = action == "Restart";
+                    let (state, device) = (state.clone(), device.clone());
+                    tokio::spawn(async move {
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        state.detach(&device.udid);
+                        if restart {
+                            tokio::time::sleep(Duration::from_millis(700)).await;
+                            state.attach(device);
+                        }
+                    });
+                }
+                continue;
+            }
+            Some("Goodbye") => {
+                res.insert("Status".into(), "Success".into());
+                write_frame(&mut stream, res).await?;
+                return Ok(());
+            }
+            _ => {
+                res.insert("Status".into(), "UnknownRequest".into());
+            }
+        }
+        write_frame(&mut stream, res).await?;
+    }
+}
+
+async fn read_mux_packet(stream: &mut Box<dyn Stream>) -> io::Result<(u32, plist::Dictionary)> {
+    let mut header = [0u8; 16];
+    stream.read_exact(&mut header).await?;
+    let size = u32::from_le_bytes(header[..4].try_into().unwrap());
+    let tag = u32::from_le_bytes(header[12..].try_into().unwrap());
+    let mut body = vec![0; size as usize - 16];
+    stream.read_exact(&mut body).await?;
+    Ok((tag, plist::from_bytes(&body).map_err(io::Error::other)?))
+}
+
+async fn write_mux_packet(
+    stream: &mut Box<dyn Stream>,
+    tag: u32,
+    res: plist::Dictionary,
+) -> io::Result<()> {
+    let mut body = Vec::new();
+    plist::to_writer_xml(&mut body, &res).map_err(io::Error::other)?;
+
+    let mut packet = Vec::with_capacity(body.len() + 16);
+    packet.extend_from_slice(&(body.len() as u32 + 16).to_le_bytes());
+    packet.extend_from_slice(&1u32.to_le_bytes());
+    packet.extend_from_slice(&8u32.to_le_bytes());
+    packet.extend_from_slice(&tag.to_le_bytes());
+    packet.extend_from_slice(&body);
+    stream.write_all(&packet).await
+}
+
+async fn read_frame(stream: &mut Box<dyn Stream>) -> io::Result<plist::Dictionary> {
+    let len = stream.read_u32().await?;
+    let mut buf = vec![0; len as usize];
+    stream.read_exact(&mut buf).await?;
+    plist::from_bytes(&buf).map_err(io::Error::other)
+}
+
+async fn write_frame(stream: &mut Box<dyn Stream>, res: plist::Dictionary) -> io::Result<()> {
+    let mut body = Vec::new();
+    plist::to_writer_xml(&mut body, &res).map_err(io::Error::other)?;
+    stream.write_u32(body.len() as u32).await?;
+    stream.write_all(&body).await?;
+    stream.flush().await
+}
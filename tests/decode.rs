@@ -0,0 +1,87 @@
+// Jackson Coxson
+
+mod common;
+
+use common::registry;
+use ioreg_explorer::{
+    decode::{Decoding, DecodingStore, decode, hex_dump, reg_cells, reg_pairs, strings, u32s},
+    model::Registry,
+};
+
+fn data<'a>(reg: &'a Registry, entry: &str, key: &str) -> &'a [u8] {
+    let (_, e) = reg.entries().find(|(_, e)| e.name == entry).unwrap();
+    e.properties.get(key).and_then(|x| x.as_data()).unwrap()
+}
+
+#[test]
+fn dumps_hex_and_ascii() {
+    let lines = hex_dump(b"usb-drd,t8103\0\x01\x02\x03");
+    assert_eq!(
+        lines,
+        [
+            "00000000  75 73 62 2d 64 72 64 2c  74 38 31 30 33 00 01 02  |usb-drd,t8103...|",
+            "00000010  03                                                |.|",
+        ]
+    );
+}
+
+#[test]
+fn decodes_strings_and_integers() {
+    let reg = Registry::from(registry("ioregistry_tree.plist"));
+    let compatible = data(&reg, "pmu", "compatible");
+    assert_eq!(strings(compatible), ["pmu,t8103", "pmu,spmi"]);
+    assert_eq!(Decoding::guess("compatible", compatible), Decoding::Strings);
+    assert_eq!(
+        Decoding::guess(
+            "clock-frequencies",
+            data(&reg, "arm-io", "clock-frequencies")
+        ),
+        Decoding::Hex
+    );
+
+    assert_eq!(
+        u32s(&[1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 7]),
+        (vec![1, u32::MAX], 1)
+    );
+    let lines = decode(Decoding::U64, &[2, 0, 0, 0, 0, 0, 0, 0, 9], (2, 2));
+    assert_eq!(lines, ["[0] 0x0000000000000002 (2)", "(+1 trailing bytes)"]);
+}
+
+#[test]
+fn reg_pairs_use_the_parents_cells() {
+    let reg = Registry::from(registry("ioregistry_tree.plist"));
+    let (pmu, _) = reg.entries().find(|(_, e)| e.name == "pmu").unwrap();
+    // Nothing above pmu says, so Apple's two and two
+    assert_eq!(reg_cells(&reg, pmu), (2, 2));
+    let pairs = reg_pairs(data(&reg, "pmu", "reg"), (2, 2)).unwrap();
+    assert_eq!(pairs, (vec![(0xff00000, 0x4000)], 0));
+
+    let mut dict = reg.to_dictionary(Registry::ROOT);
+    dict.insert(
+        "#address-cells".into(),
+        plist::Value::Data(vec![1, 0, 0, 0]),
+    );
+    dict.insert("#size-cells".into(), plist::Value::Data(vec![1, 0, 0, 0]));
+    let reg = Registry::from(dict);
+    assert_eq!(reg_cells(&reg, pmu), (1, 1));
+    let pairs = reg_pairs(data(&reg, "pmu", "reg"), (1, 1)).unwrap();
+    assert_eq!(pairs, (vec![(0xff00000, 0), (0x4000, 0)], 0));
+
+    assert_eq!(reg_pairs(&[0; 24], (3, 0)), None);
+}
+
+#[test]
+fn picked_decodings_persist() {
+    let path = std::env::temp_dir().join(format!("decodings_{}.plist", uuid::Uuid::new_v4()));
+    let mut store = DecodingStore::load(&path);
+    let blob = b"\x01\x00\x00\x00";
+    assert_eq!(store.get("calibration"), None);
+    assert_eq!(store.decoding("calibration", blob), Decoding::Hex);
+
+    store.set("calibration", Decoding::U32).unwrap();
+    store.set("reg", Decoding::U64).unwrap();
+    let store = DecodingStore::load(&path);
+    assert_eq!(store.decoding("calibration", blob), Decoding::U32);
+    assert_eq!(store.get("reg"), Some(Decoding::U64));
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,131 @@
+// Jackson Coxson
+
+mod common;
+
+use common::registry;
+use ioreg_explorer::{
+    diff::{ChangeKind, changed_paths, diff},
+    registry::{CHILDREN_KEY, PathSegment},
+};
+
+/// The children array of the entry reached by following first children `depth` times
+fn children_at(reg: &mut plist::Dictionary, depth: usize) -> &mut Vec<plist::Value> {
+    let mut entry = reg;
+    for _ in 0..depth {
+        entry = entry
+            .get_mut(CHILDREN_KEY)
+            .and_then(|x| x.as_array_mut())
+            .unwrap()[0]
+            .as_dictionary_mut()
+            .unwrap();
+    }
+    entry
+        .get_mut(CHILDREN_KEY)
+        .and_then(|x| x.as_array_mut())
+        .unwrap()
+}
+
+#[test]
+fn identical_snapshots_have_no_changes() {
+    assert!(
+        diff(
+            &registry("ioregistry_tree.plist").into(),
+            &registry("ioregistry_tree.plist").into()
+        )
+        .is_empty()
+    );
+}
+
+#[test]
+fn reports_changed_added_and_removed() {
+    let old = registry("ioregistry_tree.plist");
+    let mut new = registry("ioregistry_tree.plist");
+
+    // AppleT8103IO's children are pmu and usb-drd0
+    let io_children = children_at(&mut new, 3);
+    let pmu = io_children[0].as_dictionary_mut().unwrap();
+    let charger = children_at(pmu, 0)[0].as_dictionary_mut().unwrap();
+    charger.insert("CycleCount".into(), 413.into());
+    charger.remove("BatteryInstalled");
+    charger.insert("ExternalConnected".into(), true.into());
+
+    // Drop usb-drd0 and put a new entry in front of pmu
+    io_children.pop();
+    let mut spi = plist::Dictionary::new();
+    spi.insert("IORegistryEntryName".into(), "spi0".into());
+    spi.insert("IOObjectClass".into(), "AppleSPIController".into());
+    io_children.insert(0, spi.into());
+
+    let changes = diff(&old.into(), &new.into());
+    let summary: Vec<_> = changes
+        .iter()
+        .map(|c| (c.kind, c.entry, c.path.last().unwrap().as_str()))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (ChangeKind::Added, true, "spi0"),
+            (ChangeKind::Removed, false, "BatteryInstalled"),
+            (ChangeKind::Changed, false, "CycleCount"),
+            (ChangeKind::Added, false, "ExternalConnected"),
+            (ChangeKind::Removed, true, "usb-drd0@2280000"),
+        ]
+    );
+
+    let cycles = &changes[2];
+    assert_eq!(
+        cycles.path_string(),
+        "Root/AppleARMPE/arm-io@10F00000/AppleT8103IO/pmu@F00000/AppleARMPMUCharger/CycleCount"
+    );
+    assert_eq!(cycles.old, Some(412.into()));
+    assert_eq!(cycles.new, Some(413.into()));
+}
+
+#[test]
+fn nested_values_diff_by_key_and_index() {
+    let old = registry("ioregistry_tree.plist");
+    let mut new = registry("ioregistry_tree.plist");
+    let armpe = children_at(&mut new, 0)[0].as_dictionary_mut().unwrap();
+    armpe
+        .get_mut("IOPlatformArgs")
+        .and_then(|x| x.as_array_mut())
+        .unwrap()
+        .push(5.into());
+
+    let changes = diff(&old.into(), &new.into());
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].kind, ChangeKind::Added);
+    assert_eq!(
+        changes[0].path_string(),
+        "Root/AppleARMPE/IOPlatformArgs/[4]"
+    );
+}
+
+#[test]
+fn changed_paths_follow_positions() {
+    let old = registry("ioregistry_tree.plist");
+    let mut new = registry("ioregistry_tree.plist");
+    new.insert(
+        "IOKitBuildVersion".into(),
+        "Darwin Kernel Version 24.0.0".into(),
+    );
+    let armpe = children_at(&mut new, 0)[0].as_dictionary_mut().unwrap();
+    armpe
+        .get_mut("IOPlatformArgs")
+        .and_then(|x| x.as_array_mut())
+        .unwrap()[2] = 7.into();
+
+    let changed = changed_paths(&old.into(), &new.into());
+    assert_eq!(
+        changed,
+        [
+            vec![PathSegment::Key("IOKitBuildVersion".into())],
+            vec![
+                PathSegment::Key(CHILDREN_KEY.into()),
+                PathSegment::Index(0),
+                PathSegment::Key("IOPlatformArgs".into()),
+                PathSegment::Index(2),
+            ],
+        ]
+    );
+}
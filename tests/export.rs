@@ -0,0 +1,113 @@
+// Jackson Coxson
+
+mod common;
+
+use std::time::{Duration, SystemTime};
+
+use ioreg_explorer::{
+    export::{DataEncoding, ExportFormat, export, import},
+    model::Registry,
+};
+
+fn registry() -> plist::Dictionary {
+    let mut reg = common::registry("ioregistry_tree.plist");
+    reg.insert(
+        "Blob".into(),
+        plist::Value::Data(vec![0xde, 0xad, 0xbe, 0xef]),
+    );
+    reg.insert(
+        "Built".into(),
+        plist::Date::from(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)).into(),
+    );
+    reg.insert("Huge".into(), u64::MAX.into());
+    reg.insert("Negative".into(), (-42i64).into());
+    reg.insert("NotANumber".into(), f64::NAN.into());
+    reg
+}
+
+#[test]
+fn plists_round_trip() {
+    let reg = registry();
+    for format in [ExportFormat::XmlPlist, ExportFormat::BinaryPlist] {
+        let bytes = export(&Registry::from(reg.clone()), format, DataEncoding::Base64).unwrap();
+        let back: plist::Dictionary = plist::from_bytes(&bytes).unwrap();
+        // NaN never equals itself, so compare everything else
+        let strip = |mut d: plist::Dictionary| {
+            d.remove("NotANumber");
+            d
+        };
+        assert_eq!(strip(back), strip(reg.clone()), "{}", format.name());
+    }
+}
+
+#[test]
+fn json_keeps_types_faithful() {
+    let bytes = export(&registry().into(), ExportFormat::Json, DataEncoding::Hex).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+
+    assert_eq!(json["Blob"], "deadbeef");
+    assert_eq!(json["Built"], "2023-11-14T22:13:20Z");
+    assert_eq!(json["Huge"].as_u64(), Some(u64::MAX));
+    assert_eq!(json["Negative"].as_i64(), Some(-42));
+    assert_eq!(json["NotANumber"], "NaN");
+    assert_eq!(
+        json["IORegistryEntryChildren"][0]["IORegistryEntryName"],
+        "AppleARMPE"
+    );
+
+    let bytes = export(&registry().into(), ExportFormat::Json, DataEncoding::Base64).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(json["Blob"], "3q2+7w==");
+}
+
+#[test]
+fn yaml_matches_json() {
+    let reg = registry();
+    let yaml = export(
+        &Registry::from(reg.clone()),
+        ExportFormat::Yaml,
+        DataEncoding::Base64,
+    )
+    .unwrap();
+    let json = export(
+        &Registry::from(reg.clone()),
+        ExportFormat::Json,
+        DataEncoding::Base64,
+    )
+    .unwrap();
+    let from_yaml: serde_json::Value = serde_yaml_ng::from_slice(&yaml).unwrap();
+    let from_json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(from_yaml, from_json);
+}
+
+#[test]
+fn parses_format_names() {
+    assert_eq!("yml".parse(), Ok(ExportFormat::Yaml));
+    assert_eq!("binary".parse(), Ok(ExportFormat::BinaryPlist));
+    assert!("toml".parse::<ExportFormat>().is_err());
+    assert_eq!("hex".parse(), Ok(DataEncoding::Hex));
+}
+
+#[test]
+fn dumps_load_back() {
+    let reg = registry();
+    for format in ExportFormat::ALL {
+        let bytes = export(&Registry::from(reg.clone()), format, DataEncoding::Hex).unwrap();
+        let back = import(&bytes).unwrap();
+        assert_eq!(back.len(), reg.len(), "{}", format.name());
+        assert_eq!(back.get("Huge"), Some(&u64::MAX.into()));
+        assert_eq!(back.get("Negative"), Some(&(-42i64).into()));
+        assert_eq!(back.get("IOKitBuildVersion"), reg.get("IOKitBuildVersion"));
+    }
+
+    // Without a Data type, text formats give back what they wrote
+    let bytes = export(
+        &Registry::from(reg.clone()),
+        ExportFormat::Json,
+        DataEncoding::Hex,
+    )
+    .unwrap();
+    let back = import(&bytes).unwrap();
+    assert_eq!(back.get("Blob"), Some(&"deadbeef".into()));
+    assert_eq!(back.get("Built"), Some(&"2023-11-14T22:13:20Z".into()));
+}
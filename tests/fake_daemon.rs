@@ -0,0 +1,220 @@
+// Jackson Coxson
+
+mod common;
+
+use std::time::Duration;
+
+use common::{FakeDaemon, FakeDevice};
+use idevice::usbmuxd::Connection;
+use ioreg_explorer::{
+    backend::{DiagnosticsQuery, RegistryBackend, UsbmuxdBackend},
+    commands::{self, GuiCommands, IdeviceCommands, explain_error},
+};
+use tokio::sync::mpsc::unbounded_channel;
+
+#[tokio::test]
+async fn lists_devices_over_tcp() {
+    let daemon = FakeDaemon::start(vec![FakeDevice::recorded()]).await;
+    let backend = UsbmuxdBackend::new(daemon.addr());
+
+    let devs = backend.list_devices().await.unwrap();
+    assert_eq!(devs.len(), 1);
+    assert_eq!(devs[0].udid, "00008030-001A2D3E0C38802E");
+    assert_eq!(devs[0].connection_type, Connection::Usb);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn lists_devices_over_unix_socket() {
+    let daemon = FakeDaemon::start_unix(vec![FakeDevice::recorded()]).await;
+    let backend = UsbmuxdBackend::new(daemon.addr());
+
+    let devs = backend.list_devices().await.unwrap();
+    assert_eq!(devs.len(), 1);
+}
+
+#[tokio::test]
+async fn reads_lockdown_values() {
+    let daemon = FakeDaemon::start(vec![FakeDevice::recorded()]).await;
+    let backend = UsbmuxdBackend::new(daemon.addr());
+    let dev = backend.list_devices().await.unwrap().remove(0);
+
+    let values = backend.device_info(&dev).await.unwrap();
+    assert_eq!(
+        values.get("DeviceName").and_then(|x| x.as_string()),
+        Some("Test iPhone")
+    );
+}
+
+#[tokio::test]
+async fn queries_ioregistry_through_the_relay() {
+    let daemon = FakeDaemon::start(vec![FakeDevice::recorded()]).await;
+    let backend = UsbmuxdBackend::new(daemon.addr());
+    let dev = backend.list_devices().await.unwrap().remove(0);
+
+    let reg = backend
+        .ioregistry(
+            &dev,
+            Some("IOService".into()),
+            None,
+            Some("IOPMPowerSource".into()),
+        )
+        .await
+        .unwrap()
+        .expect("the fixture should match this query");
+    assert_eq!(
+        reg.get("CycleCount").and_then(|x| x.as_unsigned_integer()),
+        Some(412)
+    );
+
+    let reg = backend
+        .ioregistry(&dev, None, Some("nonexistent".into()), None)
+        .await
+        .unwrap();
+    assert!(reg.is_none());
+}
+
+#[tokio::test]
+async fn queries_other_diagnostics_through_the_relay() {
+    let daemon = FakeDaemon::start(vec![FakeDevice::recorded()]).await;
+    let backend = UsbmuxdBackend::new(daemon.addr());
+    let dev = backend.list_devices().await.unwrap().remove(0);
+
+    let gas_gauge = backend
+        .diagnostics(&dev, DiagnosticsQuery::GasGauge)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(gas_gauge.keys().collect::<Vec<_>>(), ["GasGauge"]);
+    let cycles = gas_gauge["GasGauge"].as_dictionary().unwrap()["CycleCount"].clone();
+    assert_eq!(cycles.as_unsigned_integer(), Some(412));
+
+    let all = backend
+        .diagnostics(&dev, DiagnosticsQuery::All)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        all.keys().collect::<Vec<_>>(),
+        ["GasGauge", "HDMI", "NAND", "WiFi"]
+    );
+
+    let keys = vec!["ProductType".to_string(), "ChipID".to_string()];
+    let gestalt = backend
+        .diagnostics(&dev, DiagnosticsQuery::MobileGestalt(keys))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(gestalt.len(), 2);
+    assert_eq!(
+        gestalt.get("ProductType").and_then(|x| x.as_string()),
+        Some("iPhone12,1")
+    );
+}
+
+#[tokio::test]
+async fn explains_deprecated_mobilegestalt() {
+    let mut device = FakeDevice::recorded();
+    device.gestalt = None;
+    let daemon = FakeDaemon::start(vec![device]).await;
+    let backend = UsbmuxdBackend::new(daemon.addr());
+    let dev = backend.list_devices().await.unwrap().remove(0);
+
+    let keys = vec!["ProductType".to_string()];
+    let e = backend
+        .diagnostics(&dev, DiagnosticsQuery::MobileGestalt(keys))
+        .await
+        .unwrap_err();
+    assert!(explain_error(&e).contains("no longer answers MobileGestalt"));
+}
+
+#[tokio::test]
+async fn detached_devices_refuse_connections() {
+    let daemon = FakeDaemon::start(vec![FakeDevice::recorded()]).await;
+    let backend = UsbmuxdBackend::new(daemon.addr());
+    let dev = backend.list_devices().await.unwrap().remove(0);
+
+    daemon.detach(&dev.udid);
+    assert!(backend.list_devices().await.unwrap().is_empty());
+    assert!(backend.device_info(&dev).await.is_err());
+}
+
+#[tokio::test]
+async fn command_loop_end_to_end() {
+    let daemon = FakeDaemon::start(vec![FakeDevice::recorded()]).await;
+    let (gui_sender, mut gui_recv) = unbounded_channel();
+    let (idevice_sender, idevice_receiver) = unbounded_channel();
+    tokio::spawn(commands::run(
+        UsbmuxdBackend::new(daemon.addr()),
+        idevice_receiver,
+        gui_sender,
+    ));
+
+    let mut next = async || {
+        tokio::time::timeout(Duration::from_secs(10), gui_recv.recv())
+            .await
+            .unwrap()
+            .unwrap()
+    };
+
+    idevice_sender.send(IdeviceCommands::GetDevices).unwrap();
+    let dev = match next().await {
+        GuiCommands::Devices(devs) => devs.into_values().next().unwrap().device,
+        _ => panic!("expected a device list"),
+    };
+
+    idevice_sender
+        .send(IdeviceCommands::GetDeviceInfo(dev.clone()))
+        .unwrap();
+    match next().await {
+        GuiCommands::DeviceInfo(info) => {
+            assert!(info.contains(&("iOS Version".into(), "17.5.1".into())));
+        }
+        _ => panic!("expected device info"),
+    }
+
+    idevice_sender
+        .send(IdeviceCommands::IORegistsry(
+            0,
+            (
+                dev,
+                Some("IOService".into()),
+                None,
+                Some("IOPMPowerSource".into()),
+            ),
+        ))
+        .unwrap();
+    assert!(matches!(next().await, GuiCommands::IORegistry(0, Some(_))));
+}
+
+#[tokio::test]
+async fn hotplug_updates_the_device_list() {
+    let daemon = FakeDaemon::start(vec![FakeDevice::recorded()]).await;
+    let (gui_sender, mut gui_recv) = unbounded_channel();
+    let (idevice_sender, idevice_receiver) = unbounded_channel();
+    tokio::spawn(commands::run(
+        UsbmuxdBackend::new(daemon.addr()),
+        idevice_receiver,
+        gui_sender,
+    ));
+    let mut next_devices =
+        async || match tokio::time::timeout(Duration::from_secs(10), gui_recv.recv())
+            .await
+            .unwrap()
+            .unwrap()
+        {
+            GuiCommands::Devices(devs) => devs,
+            _ => panic!("expected a device list"),
+        };
+
+    idevice_sender.send(IdeviceCommands::GetDevices).unwrap();
+    assert_eq!(next_devices().await.len(), 1);
+    // Give the listener time to subscribe
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    daemon.detach("00008030-001A2D3E0C38802E");
+    assert!(next_devices().await.is_empty());
+    daemon.attach(FakeDevice::recorded());
+    let devs = next_devices().await;
+    assert_eq!(devs.values().next().unwrap().name, "Test iPhone");
+}
@@ -0,0 +1,43 @@
+// Jackson Coxson
+
+mod common;
+
+use ioreg_explorer::index::RegistryIndex;
+
+fn index() -> RegistryIndex {
+    RegistryIndex::build(&common::registry("ioregistry_tree.plist").into())
+}
+
+#[test]
+fn lists_every_name_and_class_once() {
+    let index = index();
+    assert_eq!(
+        index.names().collect::<Vec<_>>(),
+        [
+            "AppleARMPE",
+            "AppleARMPMUCharger",
+            "AppleT8103IO",
+            "Root",
+            "arm-io",
+            "pmu",
+            "usb-drd0"
+        ]
+    );
+    assert!(index.classes().any(|c| c == "IOPlatformDevice"));
+    assert_eq!(
+        index.classes().filter(|c| *c == "AppleARMIODevice").count(),
+        1
+    );
+}
+
+#[test]
+fn counts_entries_a_query_would_find() {
+    let index = index();
+    assert_eq!(index.count(None, None), 7);
+    assert_eq!(index.count(None, Some("AppleARMIODevice")), 3);
+    // Either class key matches
+    assert_eq!(index.count(Some("arm-io"), Some("IOPlatformDevice")), 1);
+    assert_eq!(index.count(Some("pmu"), Some("AppleT8103IO")), 0);
+    // Only exact names, as the relay takes them
+    assert_eq!(index.count(Some("PMU"), None), 0);
+}
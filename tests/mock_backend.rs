@@ -0,0 +1,320 @@
+// Jackson Coxson
+
+mod common;
+
+use std::{net::Ipv4Addr, time::Duration};
+
+use common::mock::{next, phone, spawn};
+use idevice::{IdeviceError, usbmuxd::Connection};
+use ioreg_explorer::{
+    backend::{
+        PowerAction, PowerOptions, RegistryBackend,
+        mock::{MockBackend, MockDevice, MockFault},
+    },
+    commands::{self, ConnectionKind, DeviceKey, GuiCommands, IdeviceCommands},
+};
+use tokio::sync::mpsc::unbounded_channel;
+
+#[tokio::test]
+async fn lists_every_connection_by_udid() {
+    let mut wifi = phone();
+    wifi.device.connection_type = Connection::Network(Ipv4Addr::new(192, 168, 1, 20).into());
+    let backend = MockBackend::new([phone(), wifi, MockDevice::new("udid-2", 2, "Test iPhone")]);
+    let (sender, mut recv) = spawn(backend);
+
+    sender.send(IdeviceCommands::GetDevices).unwrap();
+    match next(&mut recv).await {
+        GuiCommands::Devices(devs) => {
+            // Same name, different devices, and one device twice
+            assert_eq!(devs.len(), 3);
+            let key = |udid: &str, connection| DeviceKey {
+                udid: udid.into(),
+                connection,
+            };
+            let usb = &devs[&key("00008030-001A2D3E0C38802E", ConnectionKind::Usb)];
+            assert_eq!(usb.label(), "Test iPhone (USB)");
+            let wifi = &devs[&key("00008030-001A2D3E0C38802E", ConnectionKind::Network)];
+            assert_eq!(wifi.label(), "Test iPhone (Network 192.168.1.20)");
+            assert_eq!(
+                devs[&key("udid-2", ConnectionKind::Usb)].device.device_id,
+                2
+            );
+        }
+        _ => panic!("expected a device list"),
+    }
+}
+
+#[tokio::test]
+async fn skips_devices_lockdown_refuses() {
+    let backend = MockBackend::new([
+        phone(),
+        MockDevice::new("udid-2", 2, "Locked").with_fault(MockFault::LockdownRefused),
+    ]);
+    let (sender, mut recv) = spawn(backend);
+
+    sender.send(IdeviceCommands::GetDevices).unwrap();
+    match next(&mut recv).await {
+        GuiCommands::Devices(devs) => {
+            assert_eq!(devs.len(), 1);
+            assert!(devs.values().any(|d| d.name == "Test iPhone"));
+        }
+        _ => panic!("expected a device list"),
+    }
+    match next(&mut recv).await {
+        GuiCommands::DeviceUnreachable(udid, IdeviceError::UsbConnectionRefused) => {
+            assert_eq!(udid, "udid-2");
+        }
+        _ => panic!("expected the skipped device"),
+    }
+}
+
+#[tokio::test]
+async fn reports_missing_usbmuxd() {
+    let backend = MockBackend::new([phone()]);
+    backend.set_fault(Some(MockFault::NoUsbmuxd));
+    let (sender, mut recv) = spawn(backend.clone());
+
+    sender.send(IdeviceCommands::GetDevices).unwrap();
+    assert!(matches!(next(&mut recv).await, GuiCommands::NoUsbmuxd(_)));
+
+    backend.set_fault(Some(MockFault::BadDeviceList));
+    sender.send(IdeviceCommands::GetDevices).unwrap();
+    assert!(matches!(
+        next(&mut recv).await,
+        GuiCommands::GetDevicesFailure(_)
+    ));
+}
+
+#[tokio::test]
+async fn serves_fixture_registry() {
+    let device = phone();
+    let (sender, mut recv) = spawn(MockBackend::new([device.clone()]));
+
+    sender
+        .send(IdeviceCommands::IORegistsry(
+            1,
+            (
+                device.device.clone(),
+                Some("IOService".into()),
+                None,
+                Some("IOPMPowerSource".into()),
+            ),
+        ))
+        .unwrap();
+    match next(&mut recv).await {
+        GuiCommands::IORegistry(1, Some(reg)) => {
+            assert_eq!(
+                reg.get("BatterySerialNumber").and_then(|x| x.as_string()),
+                Some("F8Y0472VJ4NLTVKAV")
+            );
+        }
+        _ => panic!("expected a registry"),
+    }
+
+    // Unknown queries come back empty, like the relay does
+    sender
+        .send(IdeviceCommands::IORegistsry(
+            2,
+            (
+                device.device.clone(),
+                Some("IODeviceTree".into()),
+                None,
+                None,
+            ),
+        ))
+        .unwrap();
+    assert!(matches!(
+        next(&mut recv).await,
+        GuiCommands::IORegistry(2, None)
+    ));
+}
+
+#[tokio::test]
+async fn relay_errors_do_not_stop_the_loop() {
+    let timing_out = MockDevice::new("udid-2", 2, "Slow").with_fault(MockFault::RelayTimeout);
+    let empty = MockDevice::new("udid-3", 3, "Empty").with_fault(MockFault::NoRegistry);
+    let (sender, mut recv) = spawn(MockBackend::new([timing_out.clone(), empty.clone()]));
+
+    sender
+        .send(IdeviceCommands::IORegistsry(
+            1,
+            (timing_out.device, None, None, None),
+        ))
+        .unwrap();
+    // Let it fail rather than be cancelled by the next query
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    sender
+        .send(IdeviceCommands::IORegistsry(
+            2,
+            (empty.device, None, None, None),
+        ))
+        .unwrap();
+
+    // The timed out query is reported, and the next one still answered
+    assert!(matches!(
+        next(&mut recv).await,
+        GuiCommands::IORegistryFailure(1, IdeviceError::Socket(_))
+    ));
+    assert!(matches!(
+        next(&mut recv).await,
+        GuiCommands::IORegistry(2, None)
+    ));
+}
+
+#[tokio::test]
+async fn missing_registries_are_not_errors_elsewhere() {
+    let empty = MockDevice::new("udid-3", 3, "Empty");
+    let backend = MockBackend::new([empty.clone()]);
+    backend.set_fault(Some(MockFault::NoRegistry));
+    assert_eq!(backend.list_devices().await.unwrap().len(), 1);
+
+    backend.set_device_fault("udid-3", Some(MockFault::NoRegistry));
+    backend
+        .power(&empty.device, PowerAction::Sleep, PowerOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(backend.power_requests().len(), 1);
+}
+
+#[tokio::test]
+async fn newer_queries_cancel_older_ones() {
+    let slow = phone().with_latency(Duration::from_millis(300));
+    let fast = MockDevice::new("udid-2", 2, "Fast").with_fault(MockFault::NoRegistry);
+    let (sender, mut recv) = spawn(MockBackend::new([slow.clone(), fast.clone()]));
+
+    sender
+        .send(IdeviceCommands::IORegistsry(
+            1,
+            (
+                slow.device,
+                Some("IOService".into()),
+                None,
+                Some("IOPMPowerSource".into()),
+            ),
+        ))
+        .unwrap();
+    sender
+        .send(IdeviceCommands::IORegistsry(
+            2,
+            (fast.device, None, None, None),
+        ))
+        .unwrap();
+
+    assert!(matches!(
+        next(&mut recv).await,
+        GuiCommands::IORegistry(2, None)
+    ));
+    // The slow one never answers
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(recv.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn power_requests_do_not_hold_up_queries() {
+    let slow = phone().with_latency(Duration::from_millis(300));
+    let fast = MockDevice::new("udid-2", 2, "Fast").with_fault(MockFault::NoRegistry);
+    let (sender, mut recv) = spawn(MockBackend::new([slow.clone(), fast.clone()]));
+
+    sender
+        .send(IdeviceCommands::Power(
+            slow.device.clone(),
+            PowerAction::Sleep,
+            PowerOptions::default(),
+        ))
+        .unwrap();
+    sender
+        .send(IdeviceCommands::GetDeviceInfo(fast.device.clone()))
+        .unwrap();
+    sender
+        .send(IdeviceCommands::IORegistsry(
+            1,
+            (fast.device, None, None, None),
+        ))
+        .unwrap();
+
+    let mut answers = Vec::new();
+    for _ in 0..3 {
+        answers.push(match next(&mut recv).await {
+            GuiCommands::DeviceInfo(_) => "info",
+            GuiCommands::IORegistry(1, None) => "query",
+            GuiCommands::PowerSent(_) => "power",
+            _ => "other",
+        });
+    }
+    answers[..2].sort();
+    assert_eq!(answers, ["info", "query", "power"]);
+}
+
+#[tokio::test]
+async fn slow_lockdown_does_not_hold_up_queries() {
+    let gone =
+        MockDevice::new("udid-2", 2, "Gone").with_lockdown_latency(Duration::from_millis(300));
+    let (sender, mut recv) = spawn(MockBackend::new([phone(), gone]));
+
+    sender.send(IdeviceCommands::GetDevices).unwrap();
+    sender
+        .send(IdeviceCommands::IORegistsry(
+            1,
+            (
+                phone().device,
+                Some("IOService".into()),
+                None,
+                Some("IOPMPowerSource".into()),
+            ),
+        ))
+        .unwrap();
+    assert!(matches!(
+        next(&mut recv).await,
+        GuiCommands::IORegistry(1, Some(_))
+    ));
+    // A second request replaces the first, and is still answered once
+    sender.send(IdeviceCommands::GetDevices).unwrap();
+    assert!(matches!(next(&mut recv).await, GuiCommands::Devices(d) if d.len() == 2));
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert!(recv.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn supervisor_restarts_after_a_panic() {
+    let backend = MockBackend::new([phone()]);
+    backend.set_fault(Some(MockFault::Panic));
+    let (gui_sender, mut recv) = unbounded_channel();
+    let (sender, idevice_receiver) = unbounded_channel();
+    tokio::spawn(commands::supervise(
+        backend.clone(),
+        idevice_receiver,
+        gui_sender,
+    ));
+
+    sender.send(IdeviceCommands::GetDevices).unwrap();
+    match next(&mut recv).await {
+        GuiCommands::BackendRestarted(reason) => assert_eq!(reason, "mock backend panicked"),
+        _ => panic!("expected a restart"),
+    }
+
+    backend.set_fault(None);
+    sender.send(IdeviceCommands::GetDevices).unwrap();
+    assert!(matches!(next(&mut recv).await, GuiCommands::Devices(d) if d.len() == 1));
+}
+
+#[tokio::test]
+async fn panicking_queries_fail_alone() {
+    let broken = MockDevice::new("udid-2", 2, "Broken").with_fault(MockFault::Panic);
+    let (sender, mut recv) = spawn(MockBackend::new([phone(), broken.clone()]));
+
+    sender
+        .send(IdeviceCommands::IORegistsry(
+            1,
+            (broken.device.clone(), None, None, None),
+        ))
+        .unwrap();
+    assert!(matches!(
+        next(&mut recv).await,
+        GuiCommands::IORegistryFailure(1, IdeviceError::InternalError(_))
+    ));
+    // The loop is still there to answer
+    sender
+        .send(IdeviceCommands::GetDeviceInfo(phone().device))
+        .unwrap();
+    assert!(matches!(next(&mut recv).await, GuiCommands::DeviceInfo(_)));
+}
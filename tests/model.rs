@@ -0,0 +1,105 @@
+// Jackson Coxson
+
+mod common;
+
+use common::registry;
+use ioreg_explorer::{
+    model::Registry,
+    registry::{CHILDREN_KEY, PathSegment},
+};
+
+#[test]
+fn entries_link_to_their_parents() {
+    let reg = Registry::new(registry("ioregistry_tree.plist"), Some("IOService"));
+    assert_eq!(reg.root().name, "Root");
+    assert_eq!(reg.root().parent, None);
+    assert!(!reg.root().properties.contains_key(CHILDREN_KEY));
+
+    let (charger, entry) = reg
+        .entries()
+        .find(|(_, e)| e.name == "AppleARMPMUCharger")
+        .unwrap();
+    assert_eq!(entry.properties.get("CycleCount"), Some(&412.into()));
+    let names: Vec<_> = reg
+        .ancestors(charger)
+        .map(|x| reg[x].name.as_str())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "AppleARMPMUCharger",
+            "pmu",
+            "AppleT8103IO",
+            "arm-io",
+            "AppleARMPE",
+            "Root"
+        ]
+    );
+    assert_eq!(
+        reg.path(charger),
+        "IOService:/AppleARMPE/arm-io@10F00000/AppleT8103IO/pmu@F00000/AppleARMPMUCharger"
+    );
+    assert_eq!(reg.path(Registry::ROOT), "IOService:/");
+    assert_eq!(reg.entry_at(&entry.node_path), Some(charger));
+    assert_eq!(reg.find(&reg.path(charger)), Some(charger));
+}
+
+#[test]
+fn finds_entries_by_path() {
+    let reg = Registry::new(registry("ioregistry_tree.plist"), Some("IOService"));
+    let pmu = reg.find("IOService:/AppleARMPE/arm-io@10F00000/AppleT8103IO/pmu@F00000");
+    assert_eq!(pmu.map(|x| reg[x].name.as_str()), Some("pmu"));
+    // The plane and locations can be left out
+    assert_eq!(reg.find("/AppleARMPE/arm-io/AppleT8103IO/pmu"), pmu);
+    assert_eq!(reg.find("IOService:/"), Some(Registry::ROOT));
+
+    assert_eq!(reg.find("IODeviceTree:/AppleARMPE"), None);
+    assert_eq!(reg.find("/AppleARMPE/arm-io@0"), None);
+}
+
+#[test]
+fn paths_leave_out_an_unknown_plane() {
+    let reg = Registry::from(registry("ioregistry_tree.plist"));
+    let armpe = reg.root().children[0];
+    assert_eq!(reg.path(armpe), "/AppleARMPE");
+
+    let args = vec![
+        PathSegment::Key(CHILDREN_KEY.into()),
+        PathSegment::Index(0),
+        PathSegment::Key("IOPlatformArgs".into()),
+    ];
+    assert_eq!(reg.entry_at(&args), Some(armpe));
+}
+
+#[test]
+fn converts_back_to_a_dictionary() {
+    let dict = registry("ioregistry_tree.plist");
+    let reg = Registry::from(dict.clone());
+    assert_eq!(reg.to_dictionary(Registry::ROOT), dict);
+}
+
+#[test]
+fn paths_below_a_queried_entry_keep_it() {
+    let mut charger = registry("ioregistry_power_source.plist");
+    let mut child = plist::Dictionary::new();
+    child.insert("IORegistryEntryName".into(), "gauge".into());
+    child.insert("IORegistryEntryLocation".into(), "1".into());
+    charger.insert(CHILDREN_KEY.into(), plist::Value::Array(vec![child.into()]));
+    let reg = Registry::new(charger, Some("IOService"));
+    assert!(!reg.is_plane_root());
+
+    let gauge = reg.root().children[0];
+    assert_eq!(reg.path(Registry::ROOT), "IOService:/…/AppleARMPMUCharger");
+    assert_eq!(reg.path(gauge), "IOService:/…/AppleARMPMUCharger/gauge@1");
+    for id in [Registry::ROOT, gauge] {
+        assert_eq!(reg.find(&reg.path(id)), Some(id));
+    }
+    // The full path from a whole plane query finds it too
+    assert_eq!(
+        reg.find(
+            "IOService:/AppleARMPE/arm-io@10F00000/AppleT8103IO/pmu@F00000/AppleARMPMUCharger/gauge"
+        ),
+        Some(gauge)
+    );
+    assert_eq!(reg.find("IOService:/gauge@1"), None);
+}
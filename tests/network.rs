@@ -0,0 +1,148 @@
+// Jackson Coxson
+
+mod common;
+
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+};
+
+use common::{FIXTURES, FakeDaemon, FakeDevice};
+use idevice::usbmuxd::Connection;
+use ioreg_explorer::{
+    backend::{NetworkDiscovery, RegistryBackend, UsbmuxdBackend},
+    pairing::PairingStore,
+};
+
+const UDID: &str = "00008030-001A2D3E0C38802E";
+const MAC: &str = "a4:83:e7:12:34:56";
+
+fn temp_store() -> PairingStore {
+    PairingStore::new(std::env::temp_dir().join(format!("pairing_{}", uuid::Uuid::new_v4())))
+}
+
+fn pairing_fixture() -> PathBuf {
+    format!("{FIXTURES}/pairing_file.plist").into()
+}
+
+#[test]
+fn imports_pairing_files() {
+    let store = temp_store();
+    assert!(store.list().is_empty());
+
+    let udid = store.import(&pairing_fixture(), None).unwrap();
+    assert_eq!(udid, UDID);
+    assert!(store.get(UDID).is_some());
+    assert!(store.get("00008030-000000000000000").is_none());
+
+    let found = store.find_by_mac(&MAC.to_uppercase()).unwrap();
+    assert_eq!(found.udid, UDID);
+    assert_eq!(store.list().len(), 1);
+
+    let not_pairing = format!("{FIXTURES}/lockdown_values.plist");
+    assert!(store.import(not_pairing.as_ref(), None).is_err());
+    assert!(
+        store
+            .import(&pairing_fixture(), Some("not a udid"))
+            .is_err()
+    );
+
+    store.delete(UDID).unwrap();
+    assert!(store.get(UDID).is_none());
+    assert!(store.delete(UDID).is_err());
+    let _ = std::fs::remove_dir_all(store.dir());
+}
+
+#[tokio::test]
+async fn falls_back_to_imported_pairing_files() {
+    let mut device = FakeDevice::recorded();
+    device.pair_record.clear();
+    let daemon = FakeDaemon::start(vec![device]).await;
+    let query = async |backend: &UsbmuxdBackend| {
+        let dev = backend.list_devices().await.unwrap().remove(0);
+        backend
+            .ioregistry(
+                &dev,
+                Some("IOService".into()),
+                None,
+                Some("IOPMPowerSource".into()),
+            )
+            .await
+    };
+
+    // usbmuxd has no record, so the session can't start
+    assert!(query(&UsbmuxdBackend::new(daemon.addr())).await.is_err());
+
+    let store = temp_store();
+    store.import(&pairing_fixture(), None).unwrap();
+    let backend = UsbmuxdBackend::new(daemon.addr()).with_pairing_store(store.clone());
+    assert!(query(&backend).await.unwrap().is_some());
+    let _ = std::fs::remove_dir_all(store.dir());
+}
+
+// Only Linux answers on all of 127.0.0.0/8 without setting up an alias, and the
+// device has to be on an address of its own to listen on lockdown's port
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn connects_to_network_devices_directly() {
+    use common::FakeNetworkDevice;
+    use idevice::usbmuxd::UsbmuxdAddr;
+
+    let ip = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2));
+    let _device = FakeNetworkDevice::start(ip, FakeDevice::recorded()).await;
+    let store = temp_store();
+    store.import(&pairing_fixture(), None).unwrap();
+    let discovery = NetworkDiscovery::default();
+    discovery.insert(MAC, ip);
+
+    // Nothing listens here, so only the network device can show up
+    let addr = UsbmuxdAddr::TcpSocket("127.0.0.1:1".parse().unwrap());
+    let backend = UsbmuxdBackend::new(addr).with_network(discovery, store.clone());
+
+    let devs = backend.list_devices().await.unwrap();
+    assert_eq!(devs.len(), 1);
+    assert_eq!(devs[0].udid, UDID);
+    assert_eq!(devs[0].connection_type, Connection::Network(ip));
+
+    let values = backend.device_info(&devs[0]).await.unwrap();
+    assert_eq!(
+        values.get("DeviceName").and_then(|x| x.as_string()),
+        Some("Test iPhone")
+    );
+    let reg = backend
+        .ioregistry(
+            &devs[0],
+            Some("IOService".into()),
+            None,
+            Some("IOPMPowerSource".into()),
+        )
+        .await
+        .unwrap();
+    assert!(reg.is_some());
+    let _ = std::fs::remove_dir_all(store.dir());
+}
+
+#[tokio::test]
+async fn lists_usb_and_network_connections_of_a_device() {
+    let daemon = FakeDaemon::start(vec![FakeDevice::recorded()]).await;
+    let store = temp_store();
+    store.import(&pairing_fixture(), None).unwrap();
+    let discovery = NetworkDiscovery::default();
+    discovery.insert(MAC, IpAddr::V4(Ipv4Addr::new(127, 0, 0, 3)));
+    let backend = UsbmuxdBackend::new(daemon.addr()).with_network(discovery, store.clone());
+
+    let devs = backend.list_devices().await.unwrap();
+    assert_eq!(devs.len(), 2);
+    assert_eq!(devs[0].connection_type, Connection::Usb);
+    assert_eq!(devs[1].udid, devs[0].udid);
+    assert!(matches!(devs[1].connection_type, Connection::Network(_)));
+    let _ = std::fs::remove_dir_all(store.dir());
+}
+
+#[test]
+fn unknown_devices_are_not_listed() {
+    let discovery = NetworkDiscovery::default();
+    discovery.insert("00:11:22:33:44:55", IpAddr::V4(Ipv4Addr::LOCALHOST));
+    assert_eq!(discovery.devices().len(), 1);
+    assert!(temp_store().find_by_mac("00:11:22:33:44:55").is_none());
+}
@@ -0,0 +1,64 @@
+// Jackson Coxson
+
+mod common;
+
+use common::registry;
+use ioreg_explorer::{
+    model::Registry,
+    registry::{CHILDREN_KEY, PathSegment},
+    search::{MatchKind, Query, search},
+};
+
+fn child(i: usize) -> [PathSegment; 2] {
+    [PathSegment::Key(CHILDREN_KEY.into()), PathSegment::Index(i)]
+}
+
+#[test]
+fn empty_query_is_none() {
+    assert_eq!(Query::parse("   "), None);
+}
+
+#[test]
+fn plain_text_matches_names_classes_keys_and_values() {
+    let reg = Registry::from(registry("ioregistry_tree.plist"));
+
+    // Entry name, and its class is only reported once
+    let m = search(&reg, &Query::parse("ARM-IO").unwrap());
+    assert_eq!(m.len(), 1);
+    assert_eq!(m[0].kind, MatchKind::EntryName);
+    assert_eq!(m[0].path, [child(0), child(0)].concat());
+
+    // Key and value, in tree order
+    let m = search(&reg, &Query::parse("cyclecount").unwrap());
+    assert_eq!(m.len(), 1);
+    assert_eq!(m[0].kind, MatchKind::Key);
+    let m = search(&reg, &Query::parse("F8Y0472").unwrap());
+    assert_eq!(m.len(), 1);
+    assert_eq!(m[0].kind, MatchKind::Value);
+    assert_eq!(
+        m[0].path.last(),
+        Some(&PathSegment::Key("BatterySerialNumber".into()))
+    );
+
+    // Numbers match as text
+    let m = search(&reg, &Query::parse("2931").unwrap());
+    assert_eq!(m.len(), 1);
+
+    // Either class matches the entry, once: arm-io, pmu and usb-drd0
+    let m = search(&reg, &Query::parse("AppleARMIODevice").unwrap());
+    assert_eq!(m.len(), 3);
+    assert!(m.iter().all(|m| m.kind == MatchKind::EntryClass));
+}
+
+#[test]
+fn key_value_only_matches_properties() {
+    let reg = Registry::from(registry("ioregistry_tree.plist"));
+    let m = search(&reg, &Query::parse("IOClass = AppleARMIODevice").unwrap());
+    // arm-io, pmu and usb-drd0
+    assert_eq!(m.len(), 3);
+    assert!(m.iter().all(|m| m.kind == MatchKind::Value));
+    assert!(m[0].path.len() < m[1].path.len());
+
+    let m = search(&reg, &Query::parse("CycleCount=999").unwrap());
+    assert!(m.is_empty());
+}
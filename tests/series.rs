@@ -0,0 +1,109 @@
+// Jackson Coxson
+
+mod common;
+
+use std::time::{Duration, SystemTime};
+
+use common::registry;
+use ioreg_explorer::{
+    model::Registry,
+    registry::{CHILDREN_KEY, PathSegment},
+    series::Series,
+};
+
+/// Root/AppleARMPE/arm-io/AppleT8103IO/pmu/AppleARMPMUCharger/`key`
+fn charger_path(key: &str) -> Vec<PathSegment> {
+    let mut path = Vec::new();
+    for _ in 0..5 {
+        path.push(PathSegment::Key(CHILDREN_KEY.into()));
+        path.push(PathSegment::Index(0));
+    }
+    path.push(PathSegment::Key(key.into()));
+    path
+}
+
+#[test]
+fn labels_name_entries() {
+    let reg = Registry::from(registry("ioregistry_tree.plist"));
+    let path = charger_path("CycleCount");
+    assert_eq!(reg.lookup(&path), Some(&412.into()));
+    assert_eq!(
+        reg.path_label(&path),
+        "AppleARMPE/arm-io/AppleT8103IO/pmu/AppleARMPMUCharger/CycleCount"
+    );
+
+    let args = vec![
+        PathSegment::Key(CHILDREN_KEY.into()),
+        PathSegment::Index(0),
+        PathSegment::Key("IOPlatformArgs".into()),
+        PathSegment::Index(2),
+    ];
+    assert_eq!(reg.path_label(&args), "AppleARMPE/IOPlatformArgs/[2]");
+}
+
+#[test]
+fn only_numbers_can_be_pinned() {
+    let reg = Registry::from(registry("ioregistry_tree.plist"));
+    let now = SystemTime::now();
+    assert!(Series::pin(&reg, &charger_path("BatterySerialNumber"), now).is_none());
+    assert!(Series::pin(&reg, &charger_path("Missing"), now).is_none());
+    assert!(Series::pin(&reg, &charger_path("InstantAmperage"), now).is_some());
+}
+
+#[test]
+fn records_stats_and_csv() {
+    let mut reg = registry("ioregistry_tree.plist");
+    let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let path = charger_path("InstantAmperage");
+    let mut series = Series::pin(&Registry::from(reg.clone()), &path, start).unwrap();
+
+    for (i, amps) in [-300, 1500].into_iter().enumerate() {
+        let charger = reg
+            .get_mut(CHILDREN_KEY)
+            .and_then(|x| x.as_array_mut())
+            .unwrap();
+        let mut entry = charger[0].as_dictionary_mut().unwrap();
+        for _ in 0..4 {
+            entry = entry
+                .get_mut(CHILDREN_KEY)
+                .and_then(|x| x.as_array_mut())
+                .unwrap()[0]
+                .as_dictionary_mut()
+                .unwrap();
+        }
+        entry.insert("InstantAmperage".into(), amps.into());
+        let time = start + Duration::from_secs(i as u64 + 1);
+        assert!(series.record(&Registry::from(reg.clone()), time));
+    }
+
+    let stats = series.stats().unwrap();
+    assert_eq!(stats.min, -300.0);
+    assert_eq!(stats.max, 1500.0);
+    assert_eq!(stats.avg, (812.0 - 300.0 + 1500.0) / 3.0);
+
+    let csv = series.to_csv();
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next(),
+        Some(
+            "timestamp,unix_time,AppleARMPE/arm-io/AppleT8103IO/pmu/AppleARMPMUCharger/InstantAmperage"
+        )
+    );
+    assert_eq!(
+        lines.next(),
+        Some("2023-11-14T22:13:20Z,1700000000.000,812")
+    );
+    assert_eq!(lines.count(), 2);
+}
+
+#[test]
+fn quotes_csv_headers() {
+    let mut reg = registry("ioregistry_tree.plist");
+    reg.insert("Cell \"A\", mV".into(), 3800.into());
+    let path = vec![PathSegment::Key("Cell \"A\", mV".into())];
+    let series = Series::pin(&Registry::from(reg), &path, SystemTime::UNIX_EPOCH).unwrap();
+    assert_eq!(
+        series.to_csv().lines().next(),
+        Some("timestamp,unix_time,\"Cell \"\"A\"\", mV\"")
+    );
+}
@@ -0,0 +1,142 @@
+// Jackson Coxson
+#![cfg(feature = "gui")]
+
+mod common;
+
+use common::registry;
+use ioreg_explorer::{
+    model::Registry,
+    registry::{CHILDREN_KEY, PathSegment},
+    search::{Query, search},
+    tree_view::{RegistryTree, RowKind},
+};
+
+#[test]
+fn root_starts_expanded() {
+    let reg = Registry::from(registry("ioregistry_tree.plist"));
+    let mut tree = RegistryTree::default();
+    let rows = tree.rows(&reg);
+
+    assert_eq!(rows[0].kind, RowKind::Entry);
+    assert_eq!(rows[0].label, "Root");
+    assert_eq!(rows[0].value, "IORegistryEntry");
+
+    // Properties first, then the single child entry, still collapsed
+    let last = rows.last().unwrap();
+    assert_eq!(last.kind, RowKind::Entry);
+    assert_eq!(last.label, "AppleARMPE");
+    assert_eq!(last.depth, 1);
+    assert!(rows.iter().all(|r| r.label != CHILDREN_KEY));
+    assert!(rows.iter().all(|r| r.label != "arm-io"));
+}
+
+#[test]
+fn children_nest_under_their_entry() {
+    let reg = Registry::from(registry("ioregistry_tree.plist"));
+    let mut tree = RegistryTree::default();
+    let armpe = vec![PathSegment::Key(CHILDREN_KEY.into()), PathSegment::Index(0)];
+    tree.toggle(&armpe);
+    assert!(tree.is_expanded(&armpe));
+
+    let rows = tree.rows(&reg);
+    let arm_io = rows.iter().find(|r| r.label == "arm-io").unwrap();
+    assert_eq!(arm_io.depth, 2);
+    assert_eq!(arm_io.value, "IOPlatformDevice");
+    let platform_args = rows.iter().find(|r| r.label == "IOPlatformArgs").unwrap();
+    assert_eq!(platform_args.value, "[4 items]");
+    assert!(platform_args.expandable);
+}
+
+#[test]
+fn expand_and_collapse_all() {
+    let reg = Registry::from(registry("ioregistry_tree.plist"));
+    let mut tree = RegistryTree::default();
+
+    tree.expand_all(&reg);
+    let rows = tree.rows(&reg);
+    let charger = rows
+        .iter()
+        .find(|r| r.label == "AppleARMPMUCharger" && r.kind == RowKind::Entry)
+        .unwrap();
+    assert_eq!(charger.depth, 5);
+    assert!(rows.iter().any(|r| r.label == "[3]"));
+    let cycles = rows.iter().find(|r| r.label == "CycleCount").unwrap();
+    assert_eq!(cycles.value, "412");
+    assert!(!cycles.data);
+    assert!(rows.iter().any(|r| r.label == "reg" && r.data));
+
+    tree.collapse_all();
+    assert_eq!(tree.rows(&reg).len(), 1);
+}
+
+#[test]
+fn goes_to_pasted_paths() {
+    let reg = Registry::new(registry("ioregistry_tree.plist"), Some("IOService"));
+    let mut tree = RegistryTree::default();
+
+    let id = tree
+        .go_to(&reg, "IOService:/AppleARMPE/arm-io@10F00000/AppleT8103IO")
+        .unwrap();
+    assert_eq!(reg[id].name, "AppleT8103IO");
+    assert_eq!(tree.selected(), Some(&reg[id].node_path));
+    let rows = tree.rows(&reg);
+    let io = rows.iter().find(|r| r.label == "AppleT8103IO").unwrap();
+    assert_eq!(io.depth, 3);
+    // Only what's above it is opened
+    assert!(rows.iter().all(|r| r.label != "pmu"));
+
+    // Other planes and missing entries are errors, and leave the selection alone
+    assert!(tree.go_to(&reg, "IODeviceTree:/arm-io").is_err());
+    assert!(tree.go_to(&reg, "/AppleARMPE/spi0").is_err());
+    assert_eq!(tree.selected(), Some(&reg[id].node_path));
+}
+
+#[test]
+fn copied_paths_go_back_to_a_queried_entry() {
+    // What an IOService query for the entry pmu answers with
+    let whole = Registry::from(registry("ioregistry_tree.plist"));
+    let pmu = whole.find("/AppleARMPE/arm-io/AppleT8103IO/pmu").unwrap();
+    let reg = Registry::new(whole.to_dictionary(pmu), Some("IOService"));
+    let mut tree = RegistryTree::default();
+
+    let charger = reg.root().children[0];
+    tree.select(&reg[charger].node_path);
+    let copied = tree.selected_path(&reg).unwrap();
+    assert_eq!(copied, "IOService:/…/pmu@F00000/AppleARMPMUCharger");
+
+    tree.select(&reg.root().node_path);
+    assert_eq!(tree.go_to(&reg, &copied), Ok(charger));
+    assert_eq!(tree.selected(), Some(&reg[charger].node_path));
+}
+
+#[test]
+fn filter_hides_unmatched_branches() {
+    let reg = Registry::from(registry("ioregistry_tree.plist"));
+    let matches = search(&reg, &Query::parse("BatteryInstalled").unwrap());
+    assert_eq!(matches.len(), 1);
+
+    let mut tree = RegistryTree::default();
+    tree.set_matches(&matches, true);
+    let rows = tree.rows(&reg);
+    let labels: Vec<_> = rows.iter().map(|r| r.label.as_str()).collect();
+    assert_eq!(
+        labels,
+        [
+            "Root",
+            "AppleARMPE",
+            "arm-io",
+            "AppleT8103IO",
+            "pmu",
+            "AppleARMPMUCharger",
+            "BatteryInstalled"
+        ]
+    );
+    assert!(rows.last().unwrap().highlighted);
+
+    tree.set_matches(&matches, false);
+    assert!(
+        tree.rows(&reg)
+            .iter()
+            .any(|r| r.label == "IOKitBuildVersion")
+    );
+}